@@ -1,15 +1,29 @@
 use std::str::FromStr;
+use std::time::Duration;
 
 use anyhow::{anyhow, Context};
+use chrono::{DateTime, FixedOffset};
 
-#[derive(Debug, PartialEq)]
+use crate::error::DsmrError;
+use crate::telegram::{Options, Profile};
+use crate::timestamp::Timezone;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Unit {
+    Watt,
     KiloWatt,
+    WattHour,
     KiloWattHour,
     Ampere,
     Volt,
+    CubicDecimeters,
     CubicMeters,
+    GigaJoule,
     Seconds,
+    Var,
+    KiloVar,
+    VarHour,
+    KiloVarHour,
 }
 
 impl FromStr for Unit {
@@ -17,47 +31,123 @@ impl FromStr for Unit {
 
     fn from_str(text: &str) -> Result<Self, Self::Err> {
         match text {
-            "kW"    => Ok(Self::KiloWatt),
-            "kWh"   => Ok(Self::KiloWattHour),
-            "A"     => Ok(Self::Ampere),
-            "V"     => Ok(Self::Volt),
-            "m3"    => Ok(Self::CubicMeters),
-            "s"     => Ok(Self::Seconds),
-             _      => Err(anyhow!("Unknown unit {text:?}")),
+            "W"               => Ok(Self::Watt),
+            "kW"              => Ok(Self::KiloWatt),
+            "Wh"              => Ok(Self::WattHour),
+            "kWh"             => Ok(Self::KiloWattHour),
+            "A"               => Ok(Self::Ampere),
+            "V"               => Ok(Self::Volt),
+            "dm3"             => Ok(Self::CubicDecimeters),
+            "m3"              => Ok(Self::CubicMeters),
+            "GJ"              => Ok(Self::GigaJoule),
+            "s"               => Ok(Self::Seconds),
+            "var" | "VAr"     => Ok(Self::Var),
+            "kvar" | "kVAr"   => Ok(Self::KiloVar),
+            "varh" | "VArh"   => Ok(Self::VarHour),
+            "kvarh" | "kVArh" => Ok(Self::KiloVarHour),
+            _                 => Err(anyhow!("Unknown unit {text:?}")),
+        }
+    }
+}
+
+impl Unit {
+    /// Convert a value in this unit to `target`, if both measure the same quantity.
+    pub fn convert(self, value: f64, target: Unit) -> Option<f64> {
+        match (self, target) {
+            (a, b) if a == b                            => Some(value),
+            (Self::Watt, Self::KiloWatt)
+            | (Self::WattHour, Self::KiloWattHour)
+            | (Self::CubicDecimeters, Self::CubicMeters)
+            | (Self::Var, Self::KiloVar)
+            | (Self::VarHour, Self::KiloVarHour)        => Some(value / 1000.0),
+            (Self::KiloWatt, Self::Watt)
+            | (Self::KiloWattHour, Self::WattHour)
+            | (Self::CubicMeters, Self::CubicDecimeters)
+            | (Self::KiloVar, Self::Var)
+            | (Self::KiloVarHour, Self::VarHour)        => Some(value * 1000.0),
+            (Self::KiloWattHour, Self::GigaJoule)       => Some(value * 0.0036),
+            (Self::GigaJoule, Self::KiloWattHour)       => Some(value / 0.0036),
+            _                                           => None,
         }
     }
 }
 
+/// M-Bus device types (EN 13757-3) as reported in `0-n:24.1.0`.
+pub mod device_type {
+    pub const GAS: u8           = 0x03;
+    pub const HEAT: u8          = 0x04;
+    pub const WATER: u8         = 0x07;
+    pub const COLD: u8          = 0x0a;
+    pub const COLD_INLET: u8    = 0x0b;
+    pub const HEAT_INLET: u8    = 0x0c;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PowerFailureEvent {
+    pub ended_at: DateTime<FixedOffset>,
+    pub duration: Duration,
+}
+
+/// Peak of the quarter-hourly average demand during one month (Belgian e-MUCS).
+#[derive(Debug, Clone, PartialEq)]
+pub struct MonthlyPeak {
+    pub month: DateTime<FixedOffset>,
+    pub timestamp: Option<DateTime<FixedOffset>>,
+    pub demand: f64,
+}
+
 #[derive(Debug, PartialEq)]
 pub enum Attribute {
     Header(String),
     Version(String),
-    Timestamp(String),
+    Timestamp(DateTime<FixedOffset>),
     EquipmentIdentifier(String),
+    VersionInformation(String),
     ElectricityDelivered(u8, f64),
     ElectricityReceived(u8, f64),
+    ReactiveEnergyDelivered(u8, f64),
+    ReactiveEnergyReceived(u8, f64),
     TariffIndicator(i64),
     ActualPowerDelivered(f64),
     ActualPowerReceived(f64),
+    ActualReactivePowerDelivered(f64),
+    ActualReactivePowerReceived(f64),
+    CurrentAverageDemand(f64),
+    PeakDemand(DateTime<FixedOffset>, f64),
+    PeakDemandHistory(Vec<MonthlyPeak>),
+    BreakerState(u8),
+    LimiterThreshold(f64),
+    LimiterThresholdCurrent(f64),
+    FuseThreshold(u8, f64),
     PowerFailures(u32),
     PowerFailuresLong(u32),
-    PowerFailureLog(Vec<String>),
+    PowerFailureLog(Vec<PowerFailureEvent>),
     VoltageSags(u8, u16),
     VoltageSwells(u8, u16),
     TextMessage(String),
+    NumericMessage(String),
     InstantVoltage(u8, f64),
     InstantCurrent(u8, f64),
     InstantPowerDelivered(u8, f64),
     InstantPowerReceived(u8, f64),
+    InstantReactivePowerDelivered(u8, f64),
+    InstantReactivePowerReceived(u8, f64),
     GasEquipmentDeviceType(u8, u8),
     GasEquipmentIdentifier(u8, String),
-    GasDelivered(u8, String, f64),
-    // TODO: heat-pump related stuff
-    // TODO: water-related stuff
+    GasDelivered(u8, DateTime<FixedOffset>, f64),
+    GasDeliveredUncorrected(u8, DateTime<FixedOffset>, f64),
+    HeatDelivered(u8, DateTime<FixedOffset>, f64),
+    ColdDelivered(u8, DateTime<FixedOffset>, f64),
+    WaterDelivered(u8, DateTime<FixedOffset>, f64),
+    ThermalVolume(u8, DateTime<FixedOffset>, f64),
+    Unknown { obis: String, values: Vec<String> },
 }
 
 impl Attribute {
     fn parse_hex(line: &str) -> Result<String, anyhow::Error> {
+        if !line.len().is_multiple_of(2) || !line.is_ascii() {
+            return Err(anyhow!("Cannot parse line as hex string: {line:?}"));
+        }
         let bytes = (0..line.len())
             .step_by(2)
             .map(|i| u8::from_str_radix(&line[i..i+2], 16))
@@ -70,16 +160,19 @@ impl Attribute {
         Ok(string)
     }
 
-    fn parse_num_unit<T: FromStr>(value: &str) -> Result<(T, String), anyhow::Error>
-    where <T as FromStr>::Err: std::error::Error + Sync + Send + 'static
-    {
-        let index = value
-            .find('*')
-            .ok_or_else(|| anyhow!("Cannot find '*' in value {value:?}"))?;
-        let (value, unit) = value.split_at(index);
+    fn parse_unit(text: &str) -> Result<(&str, Unit), anyhow::Error> {
+        let (value, unit) = text
+            .split_once('*')
+            .ok_or_else(|| anyhow!("Cannot find '*' in value {text:?}"))?;
+        Ok((value, unit.parse()?))
+    }
+
+    fn parse_num_unit(text: &str, expected: Unit) -> Result<f64, anyhow::Error> {
+        let (value, unit) = Self::parse_unit(text)?;
         let value = value.parse()
             .with_context(|| format!("Error parsing number {value:?}"))?;
-        Ok((value, unit.into()))
+        unit.convert(value, expected)
+            .ok_or_else(|| anyhow!("Expected unit {expected:?}, got {unit:?} in value {text:?}"))
     }
 
     fn parse_num<T: FromStr>(value: &str) -> Result<T, anyhow::Error>
@@ -89,24 +182,86 @@ impl Attribute {
             .with_context(|| format!("Error parsing number {value:?}"))?;
         Ok(num)
     }
-}
 
-impl FromStr for Attribute {
-    type Err = anyhow::Error;
+    fn parse_power_failure_log(value: &[String], tz: &Timezone) -> Result<Vec<PowerFailureEvent>, anyhow::Error> {
+        let count: usize = Self::parse_num(value.first().map(String::as_str).unwrap_or_default())?;
+        let events = value.get(2..).unwrap_or_default();
+        if count.checked_mul(2) != Some(events.len()) {
+            return Err(anyhow!("Expected {count} power failure events, got {events:?}"));
+        }
+        events.chunks(2)
+            .map(|event| Ok(PowerFailureEvent {
+                ended_at: tz.parse(&event[0])?,
+                duration: Duration::from_secs(Self::parse_num_unit(&event[1], Unit::Seconds)? as u64),
+            }))
+            .collect()
+    }
+
+    fn parse_peak_history(value: &[String], tz: &Timezone) -> Result<Vec<MonthlyPeak>, anyhow::Error> {
+        let count: usize = Self::parse_num(value.first().map(String::as_str).unwrap_or_default())?;
+        let peaks = value.get(3..).unwrap_or_default();
+        if count.checked_mul(3) != Some(peaks.len()) {
+            return Err(anyhow!("Expected {count} monthly peaks, got {peaks:?}"));
+        }
+        // months without a peak have a placeholder timestamp like 632525252525W
+        peaks.chunks(3)
+            .map(|peak| Ok(MonthlyPeak {
+                month: tz.parse(&peak[0])?,
+                timestamp: tz.parse(&peak[1]).ok(),
+                demand: Self::parse_num_unit(&peak[2], Unit::KiloWatt)?,
+            }))
+            .collect()
+    }
+
+    /// Reinterpret an M-Bus reading according to the device type reported for its channel.
+    pub fn with_device_type(self, device_type: u8) -> Self {
+        match (self, device_type) {
+            (Self::HeatDelivered(n, t, v), device_type::COLD | device_type::COLD_INLET) => Self::ColdDelivered(n, t, v),
+            (Self::GasDelivered(n, t, v), device_type::WATER)                           => Self::WaterDelivered(n, t, v),
+            (Self::GasDelivered(n, t, v), device_type::HEAT | device_type::HEAT_INLET
+                                        | device_type::COLD | device_type::COLD_INLET)  => Self::ThermalVolume(n, t, v),
+            (attr, _)                                                                  => attr,
+        }
+    }
+
+    /// The M-Bus channel of this attribute, if it is an M-Bus reading.
+    pub fn channel(&self) -> Option<u8> {
+        match *self {
+            Self::GasEquipmentDeviceType(n, _)
+            | Self::GasEquipmentIdentifier(n, _)
+            | Self::GasDelivered(n, _, _)
+            | Self::GasDeliveredUncorrected(n, _, _)
+            | Self::HeatDelivered(n, _, _)
+            | Self::ColdDelivered(n, _, _)
+            | Self::WaterDelivered(n, _, _)
+            | Self::ThermalVolume(n, _, _)    => Some(n),
+            _                                 => None,
+        }
+    }
+
+    /// Parse a line, interpreting its timestamps in the configured timezone and its OBIS codes
+    /// according to the configured profile.
+    pub fn parse(line: &str, options: &Options) -> Result<Self, DsmrError> {
+        let invalid_obis = |obis: &str| DsmrError::InvalidObis { line: 0, obis: obis.into(), raw: line.into() };
 
-    fn from_str(line: &str) -> Result<Self, Self::Err> {
         // split before and after first parenthesis
         let delim = line.find('(')
-                        .ok_or_else(|| anyhow!("First parenthesis not found in value {line:?}"))?;
+                        .ok_or_else(|| invalid_obis(line))?;
         let (obis, values) = line.split_at(delim);
 
         // parse key to a [u8; 5]
         let key: [u8; 5] = obis.split(&['-', ':', '.'])
                                .map(str::parse::<u8>)
-                               .collect::<Result<Vec<u8>, _>>()?
-                               .as_slice()
-                               .try_into()
-                               .with_context(|| format!("Error parsing OBIS {obis:?}"))?;
+                               .collect::<Result<Vec<u8>, _>>()
+                               .ok()
+                               .and_then(|key| key.as_slice().try_into().ok())
+                               .ok_or_else(|| invalid_obis(obis))?;
+
+        // Kamstrup meters on Nordic HAN ports number electricity registers on channel 1
+        let key = match (options.profile, key) {
+            (Profile::Nordic, [1, 1, c, d, e])  => [1, 0, c, d, e],
+            _                                   => key,
+        };
 
         // split values to a Vec<String>
         let value: Vec<String> = values.trim_start_matches('(')
@@ -115,64 +270,140 @@ impl FromStr for Attribute {
                                        .map(|s| s.to_owned())
                                        .collect::<Vec<String>>();
 
+        Self::parse_values(obis, key, value, options)
+            .map_err(|source| DsmrError::BadValue { line: 0, obis: obis.into(), raw: line.into(), source: source.into() })
+    }
+
+    fn parse_values(obis: &str, key: [u8; 5], value: Vec<String>, options: &Options) -> Result<Self, anyhow::Error> {
+        let tz = &options.timezone;
+
+        // per-phase registers repeat groups 1 to 19 as 21 to 39, 41 to 59 and 61 to 79
+        let phase = match key[2] {
+            c @ 21..=79 if c % 20 != 0  => c / 20,
+            _                           => 0,
+        };
+
+        let arg = |i: usize| value.get(i)
+                                  .map(String::as_str)
+                                  .ok_or_else(|| anyhow!("Expected at least {} values, got {value:?}", i + 1));
+
         // try to instantiate result
         match key {
-            [1, 3, 0, 2, 8]                                         => Ok(Self::Version(value[0].clone())),
+            [1, 3, 0, 2, 8]                                         => Ok(Self::Version(arg(0)?.to_owned())),
+
+            [0, 0, 1, 0, 0]                                         => Ok(Self::Timestamp(tz.parse(arg(0)?)?)),
+
+            [0, 0, 96, 1, 1]                                        => Ok(Self::EquipmentIdentifier(Self::parse_hex(arg(0)?)?)),
+
+            [1, 0, 1, 8, n]                                         => Self::parse_num_unit(arg(0)?, Unit::KiloWattHour).map(|v| Self::ElectricityDelivered(n, v)),
+
+            [1, 0, 2, 8, n]                                         => Self::parse_num_unit(arg(0)?, Unit::KiloWattHour).map(|v| Self::ElectricityReceived(n, v)),
 
-            [0, 0, 1, 0, 0]                                         => Ok(Self::Timestamp(value[0].clone())),
+            [1, 0, 3, 8, n]                                         => Self::parse_num_unit(arg(0)?, Unit::KiloVarHour).map(|v| Self::ReactiveEnergyDelivered(n, v)),
 
-            [0, 0, 96, 1, 1]                                        => Ok(Self::EquipmentIdentifier(Self::parse_hex(&value[0])?)),
+            [1, 0, 4, 8, n]                                         => Self::parse_num_unit(arg(0)?, Unit::KiloVarHour).map(|v| Self::ReactiveEnergyReceived(n, v)),
 
-            [1, 0, 1, 8, n]     if n == 1 || n == 2                 => Self::parse_num_unit(&value[0]).map(|(v, _)| Self::ElectricityDelivered(n, v)),
+            [0, 0, 96, 1, 4]                                        => Ok(Self::VersionInformation(arg(0)?.to_owned())),
 
-            [1, 0, 2, 8, n]     if n == 1 || n == 2                 => Self::parse_num_unit(&value[0]).map(|(v, _)| Self::ElectricityReceived(n, v)),
+            [0, 0, 96, 14, 0]                                       => Ok(Self::TariffIndicator(Self::parse_num(arg(0)?)?)),
 
-            [0, 0, 96, 14, 0]                                       => Ok(Self::TariffIndicator(Self::parse_num(&value[0])?)),
+            [1, 0, 1, 7, 0]                                         => Self::parse_num_unit(arg(0)?, Unit::KiloWatt).map(Self::ActualPowerDelivered),
 
-            [1, 0, 1, 7, 0]                                         => Self::parse_num_unit(&value[0]).map(|(v, _)| Self::ActualPowerDelivered(v)),
+            [1, 0, 2, 7, 0]                                         => Self::parse_num_unit(arg(0)?, Unit::KiloWatt).map(Self::ActualPowerReceived),
 
-            [1, 0, 2, 7, 0]                                         => Self::parse_num_unit(&value[0]).map(|(v, _)| Self::ActualPowerReceived(v)),
+            [1, 0, 3, 7, 0]                                         => Self::parse_num_unit(arg(0)?, Unit::KiloVar).map(Self::ActualReactivePowerDelivered),
 
-            [0, 0, 96, 7, 21]                                       => Ok(Self::PowerFailures(Self::parse_num(&value[0])?)),
+            [1, 0, 4, 7, 0]                                         => Self::parse_num_unit(arg(0)?, Unit::KiloVar).map(Self::ActualReactivePowerReceived),
 
-            [0, 0, 96, 7, 9]                                        => Ok(Self::PowerFailuresLong(Self::parse_num(&value[0])?)),
+            [1, 0, 1, 4, 0]                                         => Self::parse_num_unit(arg(0)?, Unit::KiloWatt).map(Self::CurrentAverageDemand),
 
-            [1, 0, 99, 97, 0]                                       => Ok(Self::PowerFailureLog(value)),
+            [1, 0, 1, 6, 0]                                         => Ok(Self::PeakDemand(tz.parse(arg(0)?)?, Self::parse_num_unit(arg(1)?, Unit::KiloWatt)?)),
 
-            [1, 0, n, 32, 0]    if n == 32 || n == 52 || n == 72    => Ok(Self::VoltageSags((n - 32) / 20 + 1, Self::parse_num(&value[0])?)),
+            [0, 0, 98, 1, 0]                                        => Ok(Self::PeakDemandHistory(Self::parse_peak_history(&value, tz)?)),
 
-            [1, 0, n, 36, 0]    if n == 32 || n == 52 || n == 72    => Ok(Self::VoltageSwells((n - 32) / 20 + 1, Self::parse_num(&value[0])?)),
+            [0, 0, 96, 3, 10]                                       => Ok(Self::BreakerState(Self::parse_num(arg(0)?)?)),
 
-            [0, 0, 96, 13, 0]                                       => Ok(Self::TextMessage(value[0].clone())),
+            [0, 0, 17, 0, 0]                                        => match Self::parse_unit(arg(0)?)?.1 {
+                Unit::Ampere                                        => Self::parse_num_unit(arg(0)?, Unit::Ampere).map(Self::LimiterThresholdCurrent),
+                _                                                   => Self::parse_num_unit(arg(0)?, Unit::KiloWatt).map(Self::LimiterThreshold),
+            },
 
-            [1, 0, n, 7, 0]     if n == 32 || n == 52 || n == 72    => Self::parse_num_unit(&value[0]).map(|(v, _)| Self::InstantVoltage((n - 32) / 20 + 1, v)),
+            [1, 0, 31 | 51 | 71, 4, 0]                              => Self::parse_num_unit(arg(0)?, Unit::Ampere).map(|v| Self::FuseThreshold(phase, v)),
 
-            [1, 0, n, 7, 0]     if n == 31 || n == 51 || n == 71    => Self::parse_num_unit(&value[0]).map(|(v, _)| Self::InstantCurrent((n - 31) / 20 + 1, v)),
+            [0, 0, 96, 7, 21]                                       => Ok(Self::PowerFailures(Self::parse_num(arg(0)?)?)),
 
-            [1, 0, n, 7, 0]     if n == 21 || n == 41 || n == 61    => Self::parse_num_unit(&value[0]).map(|(v, _)| Self::InstantPowerDelivered((n - 21) / 20 + 1, v)),
+            [0, 0, 96, 7, 9]                                        => Ok(Self::PowerFailuresLong(Self::parse_num(arg(0)?)?)),
 
-            [1, 0, n, 7, 0]     if n == 22 || n == 42 || n == 62    => Self::parse_num_unit(&value[0]).map(|(v, _)| Self::InstantPowerReceived((n - 22) / 20 + 1, v)),
+            [1, 0, 99, 97, 0]                                       => Ok(Self::PowerFailureLog(Self::parse_power_failure_log(&value, tz)?)),
 
-            [0, n, 24, 1, 0]                                        => Ok(Self::GasEquipmentDeviceType(n, Self::parse_num(&value[0])?)),
+            [1, 0, 32 | 52 | 72, 32, 0]                             => Ok(Self::VoltageSags(phase, Self::parse_num(arg(0)?)?)),
 
-            [0, n, 96, 1, 0]                                        => Ok(Self::GasEquipmentIdentifier(n, Self::parse_hex(&value[0])?)),
+            [1, 0, 32 | 52 | 72, 36, 0]                             => Ok(Self::VoltageSwells(phase, Self::parse_num(arg(0)?)?)),
 
-            [0, n, 24, 2, 1]                                        => Self::parse_num_unit(&value[1]).map(|(v, _)| Self::GasDelivered(n, value[0].clone(), v)),
+            [0, 0, 96, 13, 0]                                       => Ok(Self::TextMessage(Self::parse_hex(arg(0)?)?)),
 
-            _                                                       => Err(anyhow!("Cannot parse OBIS key {key:?}")),
+            [0, 0, 96, 13, 1]                                       => Ok(Self::NumericMessage(Self::parse_hex(arg(0)?)?)),
+
+            [1, 0, 32 | 52 | 72, 7, 0]                              => Self::parse_num_unit(arg(0)?, Unit::Volt).map(|v| Self::InstantVoltage(phase, v)),
+
+            [1, 0, 31 | 51 | 71, 7, 0]                              => Self::parse_num_unit(arg(0)?, Unit::Ampere).map(|v| Self::InstantCurrent(phase, v)),
+
+            [1, 0, 21 | 41 | 61, 7, 0]                              => Self::parse_num_unit(arg(0)?, Unit::KiloWatt).map(|v| Self::InstantPowerDelivered(phase, v)),
+
+            [1, 0, 22 | 42 | 62, 7, 0]                              => Self::parse_num_unit(arg(0)?, Unit::KiloWatt).map(|v| Self::InstantPowerReceived(phase, v)),
+
+            [1, 0, 23 | 43 | 63, 7, 0]                              => Self::parse_num_unit(arg(0)?, Unit::KiloVar).map(|v| Self::InstantReactivePowerDelivered(phase, v)),
+
+            [1, 0, 24 | 44 | 64, 7, 0]                              => Self::parse_num_unit(arg(0)?, Unit::KiloVar).map(|v| Self::InstantReactivePowerReceived(phase, v)),
+
+            [0, n, 24, 1, 0]                                        => Ok(Self::GasEquipmentDeviceType(n, Self::parse_num(arg(0)?)?)),
+
+            [0, 0, 96, 1, 0]    if options.profile == Profile::Nordic
+                                                                    => Ok(Self::EquipmentIdentifier(arg(0)?.to_owned())),
+
+            [0, n, 96, 1, 0]                                        => Ok(Self::GasEquipmentIdentifier(n, Self::parse_hex(arg(0)?)?)),
+
+            [0, n, 24, 2, 1]                                        => match Self::parse_unit(arg(1)?)?.1 {
+                Unit::CubicMeters | Unit::CubicDecimeters           => Ok(Self::GasDelivered(n, tz.parse(arg(0)?)?, Self::parse_num_unit(arg(1)?, Unit::CubicMeters)?)),
+                _                                                   => Ok(Self::HeatDelivered(n, tz.parse(arg(0)?)?, Self::parse_num_unit(arg(1)?, Unit::GigaJoule)?)),
+            },
+
+            [0, n, 24, 2, 3]                                        => Ok(Self::GasDeliveredUncorrected(n, tz.parse(arg(0)?)?, Self::parse_num_unit(arg(1)?, Unit::CubicMeters)?)),
+
+            [0, n, 24, 3, 0]                                        => match value.as_slice() {
+                [timestamp, _, _, _, _, unit, reading]              => Ok(Self::GasDelivered(n, tz.parse(timestamp)?, Self::parse_num_unit(&format!("{reading}*{unit}"), Unit::CubicMeters)?)),
+                _                                                   => Err(anyhow!("Expected 7 values in DSMR 3 gas reading {value:?}")),
+            },
+
+            _                                                       => Ok(Self::Unknown { obis: obis.into(), values: value }),
         }
     }
 }
 
+impl FromStr for Attribute {
+    type Err = DsmrError;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        Self::parse(line, &Options::default())
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::Attribute;
+    use chrono::{FixedOffset, TimeZone};
+
+    use std::time::Duration;
+
+    use super::{Attribute, MonthlyPeak, PowerFailureEvent, Unit};
+    use crate::error::DsmrError;
 
     #[test]
     fn test_attribute() -> Result<(), anyhow::Error> {
+        let cet = FixedOffset::east_opt(3600).unwrap();
+        let cest = FixedOffset::east_opt(7200).unwrap();
         let tests = [
             ("1-3:0.2.8(50)",                                               Attribute::Version("50".into())),
-            ("0-0:1.0.0(220611162528S)",                                    Attribute::Timestamp("220611162528S".into())),
+            ("0-0:1.0.0(220611162528S)",                                    Attribute::Timestamp(cest.with_ymd_and_hms(2022, 6, 11, 16, 25, 28).unwrap())),
             ("0-0:96.1.1(4530313233343536373839303132333435)",              Attribute::EquipmentIdentifier("E0123456789012345".into())),
             ("1-0:1.8.1(006024.008*kWh)",                                   Attribute::ElectricityDelivered(1, 6024.008)),
             ("1-0:2.8.1(001375.008*kWh)",                                   Attribute::ElectricityReceived(1, 1375.008)),
@@ -181,7 +412,8 @@ mod tests {
             ("1-0:2.7.0(03.106*kW)",                                        Attribute::ActualPowerReceived(3.106)),
             ("0-0:96.7.21(00010)",                                          Attribute::PowerFailures(10)),
             ("0-0:96.7.9(00002)",                                           Attribute::PowerFailuresLong(2)),
-            ("1-0:99.97.0(1)(0-0:96.7.19)(180228084605W)(0000000486*s)",    Attribute::PowerFailureLog(vec!["1".into(), "0-0:96.7.19".into(), "180228084605W".into(), "0000000486*s".into()])),
+            ("1-0:99.97.0(1)(0-0:96.7.19)(180228084605W)(0000000486*s)",    Attribute::PowerFailureLog(vec![PowerFailureEvent { ended_at: cet.with_ymd_and_hms(2018, 2, 28, 8, 46, 5).unwrap(), duration: Duration::from_secs(486) }])),
+            ("1-0:99.97.0(0)(0-0:96.7.19)",                                 Attribute::PowerFailureLog(vec![])),
             ("1-0:32.32.0(00007)",                                          Attribute::VoltageSags(1, 7)),
             ("1-0:32.36.0(00001)",                                          Attribute::VoltageSwells(1, 1)),
             ("0-0:96.13.0()",                                               Attribute::TextMessage("".into())),
@@ -191,11 +423,135 @@ mod tests {
             ("1-0:22.7.0(03.059*kW)",                                       Attribute::InstantPowerReceived(1, 3.059)),
             ("0-1:24.1.0(003)",                                             Attribute::GasEquipmentDeviceType(1, 3)),
             ("0-1:96.1.0(4730313233343536373839303132333435)",              Attribute::GasEquipmentIdentifier(1, "G0123456789012345".into())),
-            ("0-1:24.2.1(220611162510S)(03814.705*m3)",                     Attribute::GasDelivered(1, "220611162510S".into(), 03814.705)),
+            ("0-1:24.2.1(220611162510S)(03814.705*m3)",                     Attribute::GasDelivered(1, cest.with_ymd_and_hms(2022, 6, 11, 16, 25, 10).unwrap(), 03814.705)),
+            ("0-0:96.13.0(48656C6C6F)",                                     Attribute::TextMessage("Hello".into())),
+            ("0-0:96.13.1(3031)",                                           Attribute::NumericMessage("01".into())),
+            ("1-0:1.8.0(001234.567*kWh)",                                   Attribute::ElectricityDelivered(0, 1234.567)),
+            ("1-0:1.8.3(000012.345*kWh)",                                   Attribute::ElectricityDelivered(3, 12.345)),
+            ("1-0:2.8.4(000000.010*kWh)",                                   Attribute::ElectricityReceived(4, 0.01)),
         ];
         for (s, e) in tests {
             assert_eq!(s.parse::<Attribute>()?, e);
         }
         Ok(())
     }
+
+    #[test]
+    fn test_unit_conversion() -> Result<(), anyhow::Error> {
+        let tests = [
+            ("1-0:1.7.0(01234*W)",                                          Attribute::ActualPowerDelivered(1.234)),
+            ("1-0:1.8.1(006024008*Wh)",                                     Attribute::ElectricityDelivered(1, 6024.008)),
+            ("0-1:24.2.1(220611162510S)(03814705*dm3)",                     Attribute::GasDelivered(1, FixedOffset::east_opt(7200).unwrap().with_ymd_and_hms(2022, 6, 11, 16, 25, 10).unwrap(), 3814.705)),
+        ];
+        for (s, e) in tests {
+            assert_eq!(s.parse::<Attribute>()?, e);
+        }
+        assert_eq!(Unit::KiloWatt.convert(1.5, Unit::Watt), Some(1500.0));
+        assert_eq!(Unit::Volt.convert(230.0, Unit::Ampere), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_reactive() -> Result<(), anyhow::Error> {
+        let tests = [
+            ("1-0:3.8.0(00000123.456*kvarh)",                               Attribute::ReactiveEnergyDelivered(0, 123.456)),
+            ("1-0:4.8.1(000456789*varh)",                                   Attribute::ReactiveEnergyReceived(1, 456.789)),
+            ("1-0:3.7.0(0000.120*kvar)",                                    Attribute::ActualReactivePowerDelivered(0.12)),
+            ("1-0:4.7.0(0000.000*kVAr)",                                    Attribute::ActualReactivePowerReceived(0.0)),
+            ("1-0:23.7.0(0000.040*kvar)",                                   Attribute::InstantReactivePowerDelivered(1, 0.04)),
+            ("1-0:64.7.0(25*var)",                                          Attribute::InstantReactivePowerReceived(3, 0.025)),
+        ];
+        for (s, e) in tests {
+            assert_eq!(s.parse::<Attribute>()?, e);
+        }
+        assert!("1-0:3.7.0(0000.120*kW)".parse::<Attribute>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_heat() -> Result<(), anyhow::Error> {
+        let cest = FixedOffset::east_opt(7200).unwrap();
+        let timestamp = cest.with_ymd_and_hms(2022, 6, 11, 16, 0, 0).unwrap();
+        assert_eq!("0-2:24.2.1(220611160000S)(00123.456*GJ)".parse::<Attribute>()?, Attribute::HeatDelivered(2, timestamp, 123.456));
+        assert_eq!("0-2:24.2.1(220611160000S)(00001000*kWh)".parse::<Attribute>()?, Attribute::HeatDelivered(2, timestamp, 3.6));
+        assert_eq!(Attribute::HeatDelivered(2, timestamp, 1.0).with_device_type(0x0a), Attribute::ColdDelivered(2, timestamp, 1.0));
+        assert_eq!(Attribute::HeatDelivered(2, timestamp, 1.0).with_device_type(0x04), Attribute::HeatDelivered(2, timestamp, 1.0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_water() {
+        let timestamp = FixedOffset::east_opt(7200).unwrap().with_ymd_and_hms(2022, 6, 11, 16, 0, 0).unwrap();
+        assert_eq!(Attribute::GasDelivered(2, timestamp, 12.5).with_device_type(0x07), Attribute::WaterDelivered(2, timestamp, 12.5));
+        assert_eq!(Attribute::GasDelivered(2, timestamp, 12.5).with_device_type(0x03), Attribute::GasDelivered(2, timestamp, 12.5));
+        assert_eq!(Attribute::GasDelivered(2, timestamp, 12.5).with_device_type(0x04), Attribute::ThermalVolume(2, timestamp, 12.5));
+        assert_eq!(Attribute::GasDelivered(2, timestamp, 12.5).with_device_type(0x0c), Attribute::ThermalVolume(2, timestamp, 12.5));
+    }
+
+    #[test]
+    fn test_dsmr3_gas() -> Result<(), anyhow::Error> {
+        let cest = FixedOffset::east_opt(7200).unwrap();
+        assert_eq!("0-1:24.3.0(120517020000)(08)(60)(1)(0-1:24.2.1)(m3)(00124.477)".parse::<Attribute>()?,
+                   Attribute::GasDelivered(1, cest.with_ymd_and_hms(2012, 5, 17, 2, 0, 0).unwrap(), 124.477));
+        assert!("0-1:24.3.0(120517020000)(08)(60)(1)(0-1:24.2.1)(m3)".parse::<Attribute>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_emucs() -> Result<(), anyhow::Error> {
+        let cet = FixedOffset::east_opt(3600).unwrap();
+        let cest = FixedOffset::east_opt(7200).unwrap();
+        let tests = [
+            ("0-0:96.1.4(50217)",                                           Attribute::VersionInformation("50217".into())),
+            ("1-0:1.4.0(02.351*kW)",                                        Attribute::CurrentAverageDemand(2.351)),
+            ("1-0:1.6.0(200509134558S)(02.589*kW)",                         Attribute::PeakDemand(cest.with_ymd_and_hms(2020, 5, 9, 13, 45, 58).unwrap(), 2.589)),
+            ("0-0:98.1.0(2)(1-0:1.6.0)(1-0:1.6.0)(200501000000S)(200423192538S)(03.695*kW)(200201000000W)(632525252525W)(00.000*kW)",
+                                                                            Attribute::PeakDemandHistory(vec![
+                                                                                MonthlyPeak { month: cest.with_ymd_and_hms(2020, 5, 1, 0, 0, 0).unwrap(), timestamp: Some(cest.with_ymd_and_hms(2020, 4, 23, 19, 25, 38).unwrap()), demand: 3.695 },
+                                                                                MonthlyPeak { month: cet.with_ymd_and_hms(2020, 2, 1, 0, 0, 0).unwrap(), timestamp: None, demand: 0.0 },
+                                                                            ])),
+            ("0-0:96.3.10(1)",                                              Attribute::BreakerState(1)),
+            ("0-0:17.0.0(999.9*kW)",                                        Attribute::LimiterThreshold(999.9)),
+            ("0-0:17.0.0(999*A)",                                           Attribute::LimiterThresholdCurrent(999.0)),
+            ("1-0:31.4.0(999*A)",                                           Attribute::FuseThreshold(1, 999.0)),
+            ("0-1:24.2.3(200512134558S)(00112.384*m3)",                     Attribute::GasDeliveredUncorrected(1, cest.with_ymd_and_hms(2020, 5, 12, 13, 45, 58).unwrap(), 112.384)),
+        ];
+        for (s, e) in tests {
+            assert_eq!(s.parse::<Attribute>()?, e);
+        }
+        assert!("0-0:98.1.0(2)(1-0:1.6.0)(1-0:1.6.0)(200501000000S)(200423192538S)(03.695*kW)".parse::<Attribute>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_wrong_unit() {
+        for s in ["1-0:1.7.0(00.123*kWh)", "1-0:32.7.0(242.6*A)", "1-0:1.8.1(006024.008*MWh)", "1-0:1.8.1(006024.008)", "0-1:24.2.1(220611162510S)(03814.705*V)"] {
+            assert!(s.parse::<Attribute>().is_err(), "{s}");
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(matches!("1-0:1.8.x(1*kWh)".parse::<Attribute>(), Err(DsmrError::InvalidObis { obis, .. }) if obis == "1-0:1.8.x"));
+        assert!(matches!("no parenthesis".parse::<Attribute>(), Err(DsmrError::InvalidObis { .. })));
+        assert!(matches!("1-0:1.8.1(abc*kWh)".parse::<Attribute>(), Err(DsmrError::BadValue { obis, raw, .. })
+                         if obis == "1-0:1.8.1" && raw == "1-0:1.8.1(abc*kWh)"));
+    }
+
+    #[test]
+    fn test_malformed_lines() {
+        let corpus = [
+            "", "(", ")", "1-0:1.8.1", "1-0:1.8.1(", "1-0:1.8.1)", "1-0:1.8.1()", "1-0:1.8.1(*)", "1-0:1.8.1(*kWh)",
+            "1-0:1.6.0(220611162528S)", "1-0:1.6.0()", "0-0:96.1.1(4)", "0-0:96.1.1(é1)", "0-0:96.1.1(4é)", "0-0:96.13.0(ZZ)",
+            "0-0:96.1.1(FF)", "0-1:24.2.1(220611162510S)", "0-1:24.2.1()", "0-1:24.2.3(220611162510S)", "0-1:24.3.0(1)(2)",
+            "1-0:99.97.0()", "1-0:99.97.0(1)", "1-0:99.97.0(1)(0-0:96.7.19)(é)", "1-0:99.97.0(99999999999999999999)",
+            "1-0:99.97.0(9223372036854775808)", "0-0:98.1.0(6148914691236517206)",
+            "0-0:98.1.0()", "0-0:98.1.0(1)(1-0:1.6.0)(1-0:1.6.0)", "0-0:17.0.0()", "0-0:17.0.0(1*x)", "0-0:1.0.0(é)",
+            "0-0:1.0.0(9999999999999S)", "0-0:1.0.0(S)", "1-0:32.7.0(1e400*V)", "1-0:32.7.0(NaN*V)", "0-1:24.1.0(-1)",
+            "1-0:99.97.0(1)(0-0:96.7.19)(180228084605W)(-1e400*s)", "256-0:1.8.1(1*kWh)", "1--0:1.8.1(1*kWh)", "é(", "1-0:1.8.é(1)",
+        ];
+        for line in corpus {
+            let _ = line.parse::<Attribute>();
+        }
+    }
 }
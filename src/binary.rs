@@ -0,0 +1,51 @@
+//! Helpers shared by the decoders of the binary protocols.
+
+use std::io::Read;
+
+use anyhow::anyhow;
+
+use crate::telegram::{Options, Profile, Telegram};
+
+/// DLMS general-glo-ciphering tag that starts every encrypted APDU.
+pub(crate) const GENERAL_GLO_CIPHERING: u8 = 0xdb;
+
+/// Deepest nesting of DLMS arrays and structures or SML lists to decode; meters use only a few
+/// levels.
+pub(crate) const MAX_DEPTH: usize = 16;
+
+pub(crate) fn read_byte<S: Read + ?Sized>(reader: &mut S) -> std::io::Result<u8> {
+    Ok(read_array::<1, S>(reader)?[0])
+}
+
+pub(crate) fn read_array<const N: usize, S: Read + ?Sized>(reader: &mut S) -> std::io::Result<[u8; N]> {
+    let mut buf = [0; N];
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+pub(crate) fn read_n<S: Read + ?Sized>(reader: &mut S, n: usize) -> std::io::Result<Vec<u8>> {
+    let mut buf = vec![0; n];
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+/// Split the first `n` bytes off `input`.
+pub(crate) fn take<'a>(input: &mut &'a [u8], n: usize) -> Result<&'a [u8], anyhow::Error> {
+    if input.len() < n {
+        return Err(anyhow!("Expected {n} bytes, got {}", input.len()));
+    }
+    let (head, tail) = input.split_at(n);
+    *input = tail;
+    Ok(head)
+}
+
+pub(crate) fn take_array<const N: usize>(input: &mut &[u8]) -> Result<[u8; N], anyhow::Error> {
+    Ok(take(input, N)?.try_into()?)
+}
+
+/// Build a telegram from the data lines rendered for the registers of a binary message. These
+/// follow DSMR conventions, with hex-encoded strings, whatever the profile in `options`.
+pub(crate) fn telegram(header: &str, lines: impl IntoIterator<Item = String>, options: &Options) -> Telegram {
+    let lines: Vec<String> = [format!("/{header}"), "".into()].into_iter().chain(lines).collect();
+    Telegram::new(&lines, &Options { profile: Profile::Dsmr, ..options.clone() })
+}
@@ -1,41 +1,87 @@
 use std::path::PathBuf;
+use std::time::Duration;
 
 use clap::{Parser, ArgGroup, Args};
 use clap_verbosity_flag::{Verbosity, InfoLevel};
 
+use dsmr_prometheus::exporter::TariffNames;
+use dsmr_prometheus::iec::Mode;
+use dsmr_prometheus::smarty::Key;
+use dsmr_prometheus::telegram::{Options, Profile, Protocol};
+use dsmr_prometheus::timestamp::Timezone;
+
 #[derive(Parser, Debug)]
 #[clap(author, version)]
 pub struct CLI {
     #[clap(flatten)]
     source: SourceArgs,
 
-    #[clap(short, long, default_value="115200")]
-    baud_rate: u32,
+    #[clap(short, long)]
+    baud_rate: Option<u32>,
+
+    /// Telegram format: dsmr3 (DSMR 2.2/3.0), dsmr4 (DSMR 4/5), hdlc (DLMS push) or sml
+    #[clap(short, long, default_value="dsmr4")]
+    pub protocol: Protocol,
+
+    /// OBIS conventions of the meter: auto, dsmr or nordic (Swedish and similar HAN ports)
+    #[clap(long, default_value="auto")]
+    pub profile: Profile,
+
+    /// Names for tariff numbers, exported as the tariff_name label, like 1=low,2=normal
+    #[clap(long, default_value="")]
+    pub tariff_names: TariffNames,
+
+    /// Consecutive unreadable telegrams after which to exit (0 to keep trying forever)
+    #[clap(long, default_value="10")]
+    pub max_failures: u32,
 
     #[clap(short, long, default_value="0.0.0.0:9194")]
     pub listen: String,
 
+    /// AES key (32 hex digits) to decrypt Luxembourg Smarty telegrams or DLMS push messages
+    #[clap(short, long)]
+    pub key: Option<Key>,
+
+    /// IEC 62056-21 mode of an optical readout: c (request readouts) or d (meter sends on its own)
+    #[clap(long, default_value="c")]
+    pub iec_mode: Mode,
+
+    /// Seconds between IEC 62056-21 mode C readouts, or the longest wait for a mode D one
+    #[clap(long, default_value="60")]
+    pub interval: u64,
+
+    /// UTC offsets for winter and summer time in meter timestamps
+    #[clap(short, long, default_value="+01:00/+02:00")]
+    pub timezone: Timezone,
+
     #[clap(flatten)]
     pub verbosity: Verbosity<InfoLevel>,
 }
 
+/// Where to read telegrams from. Builds with the async feature can read from several sources at
+/// once, all with the same protocol and key.
 #[derive(Args, Debug)]
-#[clap(group(ArgGroup::new("source").required(true)))]
+#[clap(group(ArgGroup::new("source").required(true).multiple(cfg!(feature = "async"))))]
 pub struct SourceArgs {
-    #[clap(short, long, group="source")]
-    pub connect: Option<String>,
+    #[clap(short, long, group="source", multiple_occurrences(cfg!(feature = "async")))]
+    pub connect: Vec<String>,
 
-    #[clap(short, long, group="source")]
-    pub serial: Option<String>,
+    #[clap(short, long, group="source", multiple_occurrences(cfg!(feature = "async")))]
+    pub serial: Vec<String>,
 
-    #[clap(short, long, group="source")]
-    pub file: Option<PathBuf>,
+    #[clap(short, long, group="source", multiple_occurrences(cfg!(feature = "async")))]
+    pub file: Vec<PathBuf>,
+
+    /// Serial port of an IEC 62056-21 optical probe
+    #[clap(short, long, group="source", multiple_occurrences(cfg!(feature = "async")))]
+    pub iec: Vec<String>,
 }
 
 pub enum Source {
     Socket(String),
     Serial(String, u32),
     File(PathBuf),
+    Iec(String, Mode, Duration),
 }
 
 impl std::fmt::Display for Source {
@@ -44,6 +90,8 @@ impl std::fmt::Display for Source {
             Source::Socket(ref host)            => write!(f, "TCP source {host}"),
             Source::Serial(ref tty, baud_rate)  => write!(f, "serial port source {tty} ({baud_rate} bps)"),
             Source::File(ref path)              => write!(f, "file source {path:?}"),
+            Source::Iec(ref tty, Mode::C, interval) => write!(f, "IEC 62056-21 mode C source {tty} (every {}s)", interval.as_secs()),
+            Source::Iec(ref tty, Mode::D, interval) => write!(f, "IEC 62056-21 mode D source {tty} (at least every {}s)", interval.as_secs()),
         }
     }
 }
@@ -53,11 +101,23 @@ impl CLI {
         Self::try_parse()
     }
 
-    pub fn source(&self) -> Source {
-        None.xor(self.source.connect.clone().map(Source::Socket))
-            .xor(self.source.serial.clone().map(|tty| Source::Serial(tty, self.baud_rate)))
-            .xor(self.source.file.clone().map(Source::File))
-            .unwrap()
+    /// The sources to read from; just one, unless built with the async feature.
+    pub fn sources(&self) -> Vec<Source> {
+        let baud_rate = self.baud_rate.unwrap_or_else(|| self.protocol.baud_rate());
+        let interval = Duration::from_secs(self.interval);
+        self.source.connect.iter().cloned().map(Source::Socket)
+            .chain(self.source.serial.iter().cloned().map(|tty| Source::Serial(tty, baud_rate)))
+            .chain(self.source.file.iter().cloned().map(Source::File))
+            .chain(self.source.iec.iter().cloned().map(|tty| Source::Iec(tty, self.iec_mode, interval)))
+            .collect()
+    }
+
+    pub fn options(&self) -> Options {
+        Options {
+            protocol: self.protocol,
+            timezone: self.timezone,
+            profile: self.profile,
+        }
     }
 }
 
@@ -83,6 +143,69 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_timezone() -> Result<(), clap::Error> {
+        let cli = CLI::try_parse_from(["./foo", "-c", "example.com:4000", "--timezone", "+00:00/+01:00"])?;
+        assert_eq!(cli.timezone, "+00:00/+01:00".parse().unwrap());
+        assert!(CLI::try_parse_from(["./foo", "-c", "example.com:4000", "--timezone", "CET"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_protocol() -> Result<(), clap::Error> {
+        let cli = CLI::try_parse_from(["./foo", "-s", "/dev/ttyS0", "--protocol", "dsmr3"])?;
+        assert_eq!(cli.options().protocol, Protocol::Dsmr3);
+        assert!(matches!(cli.sources()[..], [Source::Serial(_, 9600)]));
+        let cli = CLI::try_parse_from(["./foo", "-s", "/dev/ttyS0", "-p", "dsmr3", "-b", "115200"])?;
+        assert!(matches!(cli.sources()[..], [Source::Serial(_, 115200)]));
+        assert!(CLI::try_parse_from(["./foo", "-s", "/dev/ttyS0", "-p", "dsmr6"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_profile() -> Result<(), clap::Error> {
+        let cli = CLI::try_parse_from(["./foo", "-s", "/dev/ttyS0", "--profile", "nordic"])?;
+        assert_eq!(cli.options().profile, Profile::Nordic);
+        assert!(CLI::try_parse_from(["./foo", "-s", "/dev/ttyS0", "--profile", "swedish"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_tariff_names() -> Result<(), clap::Error> {
+        let cli = CLI::try_parse_from(["./foo", "-s", "/dev/ttyS0", "--tariff-names", "1=low,2=normal"])?;
+        assert_eq!(cli.tariff_names, "1=low,2=normal".parse().unwrap());
+        assert!(CLI::try_parse_from(["./foo", "-s", "/dev/ttyS0", "--tariff-names", "low"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_failures() -> Result<(), clap::Error> {
+        let cli = CLI::try_parse_from(["./foo", "-s", "/dev/ttyS0"])?;
+        assert_eq!(cli.max_failures, 10);
+        let cli = CLI::try_parse_from(["./foo", "-s", "/dev/ttyS0", "--max-failures", "0"])?;
+        assert_eq!(cli.max_failures, 0);
+        assert!(CLI::try_parse_from(["./foo", "-s", "/dev/ttyS0", "--max-failures", "-1"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_key() -> Result<(), clap::Error> {
+        let cli = CLI::try_parse_from(["./foo", "-s", "/dev/ttyS0", "--key", "000102030405060708090A0B0C0D0E0F"])?;
+        assert!(cli.key.is_some());
+        assert!(CLI::try_parse_from(["./foo", "-s", "/dev/ttyS0", "--key", "0001"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_iec() -> Result<(), clap::Error> {
+        let cli = CLI::try_parse_from(["./foo", "--iec", "/dev/ttyUSB0", "--interval", "10"])?;
+        assert!(matches!(cli.sources()[..], [Source::Iec(_, Mode::C, interval)] if interval == Duration::from_secs(10)));
+        let cli = CLI::try_parse_from(["./foo", "-i", "/dev/ttyUSB0", "--iec-mode", "d"])?;
+        assert!(matches!(cli.sources()[..], [Source::Iec(_, Mode::D, _)]));
+        assert!(CLI::try_parse_from(["./foo", "-i", "/dev/ttyUSB0", "--iec-mode", "e"]).is_err());
+        Ok(())
+    }
+
     #[test]
     fn test_source_missing() {
         assert!(CLI::try_parse_from(["./foo", "-l", "0.0.0.0:9194"]).is_err());
@@ -104,7 +227,17 @@ mod test {
     }
 
     #[test]
+    #[cfg(not(feature = "async"))]
     fn test_source_1_too_many() {
         assert!(CLI::try_parse_from(["./foo", "-l", "0.0.0.0:9194", "-c", "example.com:8000", "-s", "/dev/ttyS0"]).is_err());
+        assert!(CLI::try_parse_from(["./foo", "-l", "0.0.0.0:9194", "-c", "example.com:8000", "-c", "example.com:8001"]).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn test_sources() -> Result<(), clap::Error> {
+        let cli = CLI::try_parse_from(["./foo", "-c", "example.com:8000", "-c", "example.com:8001", "-i", "/dev/ttyUSB0"])?;
+        assert!(matches!(cli.sources()[..], [Source::Socket(_), Source::Socket(_), Source::Iec(..)]));
+        Ok(())
     }
 }
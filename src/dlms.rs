@@ -0,0 +1,410 @@
+use anyhow::{anyhow, Context};
+
+use crate::binary::{self, take, take_array, GENERAL_GLO_CIPHERING, MAX_DEPTH};
+use crate::smarty::Decryptor;
+use crate::telegram::{Options, Telegram};
+
+const DATA_NOTIFICATION: u8 = 0x0f;
+
+/// COSEM data as encoded in A-XDR.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Data {
+    Null,
+    Array(Vec<Data>),
+    Structure(Vec<Data>),
+    Boolean(bool),
+    Integer(i128),
+    Float(f64),
+    OctetString(Vec<u8>),
+    VisibleString(String),
+}
+
+/// A value pushed for an OBIS code, with its scaler and unit if the meter sent them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Register {
+    pub obis: [u8; 6],
+    pub value: Data,
+    pub scaler: i8,
+    pub unit: Option<u8>,
+}
+
+fn length(input: &mut &[u8]) -> Result<usize, anyhow::Error> {
+    match take_array::<1>(input)?[0] {
+        0x81            => Ok(take_array::<1>(input)?[0].into()),
+        0x82            => Ok(u16::from_be_bytes(take_array(input)?).into()),
+        n if n < 0x80   => Ok(n.into()),
+        n               => Err(anyhow!("Unsupported length encoding {n:#04x}")),
+    }
+}
+
+impl Data {
+    /// Decode one value from the front of `input`.
+    pub fn parse(input: &mut &[u8]) -> Result<Self, anyhow::Error> {
+        Self::parse_nested(input, 0)
+    }
+
+    fn parse_nested(input: &mut &[u8], depth: usize) -> Result<Self, anyhow::Error> {
+        let tag = take_array::<1>(input)?[0];
+        if matches!(tag, 0x01 | 0x02) && depth >= MAX_DEPTH {
+            return Err(anyhow!("Arrays and structures nested more than {MAX_DEPTH} deep"));
+        }
+        match tag {
+            0x00    => Ok(Self::Null),
+            0x01    => Ok(Self::Array(Self::parse_items(input, depth + 1)?)),
+            0x02    => Ok(Self::Structure(Self::parse_items(input, depth + 1)?)),
+            0x03    => Ok(Self::Boolean(take_array::<1>(input)?[0] != 0)),
+            0x05    => Ok(Self::Integer(i32::from_be_bytes(take_array(input)?).into())),
+            0x06    => Ok(Self::Integer(u32::from_be_bytes(take_array(input)?).into())),
+            0x09    => { let n = length(input)?; Ok(Self::OctetString(take(input, n)?.to_vec())) },
+            0x0a    => { let n = length(input)?; Ok(Self::VisibleString(String::from_utf8_lossy(take(input, n)?).into())) },
+            0x0c    => { let n = length(input)?; Ok(Self::VisibleString(String::from_utf8_lossy(take(input, n)?).into())) },
+            0x0f    => Ok(Self::Integer(i8::from_be_bytes(take_array(input)?).into())),
+            0x10    => Ok(Self::Integer(i16::from_be_bytes(take_array(input)?).into())),
+            0x11    => Ok(Self::Integer(u8::from_be_bytes(take_array(input)?).into())),
+            0x12    => Ok(Self::Integer(u16::from_be_bytes(take_array(input)?).into())),
+            0x14    => Ok(Self::Integer(i64::from_be_bytes(take_array(input)?).into())),
+            0x15    => Ok(Self::Integer(u64::from_be_bytes(take_array(input)?).into())),
+            0x16    => Ok(Self::Integer(u8::from_be_bytes(take_array(input)?).into())),
+            0x17    => Ok(Self::Float(f32::from_be_bytes(take_array(input)?).into())),
+            0x18    => Ok(Self::Float(f64::from_be_bytes(take_array(input)?))),
+            0x19    => Ok(Self::OctetString(take(input, 12)?.to_vec())),
+            _       => Err(anyhow!("Unsupported data type {tag:#04x}")),
+        }
+    }
+
+    fn parse_items(input: &mut &[u8], depth: usize) -> Result<Vec<Self>, anyhow::Error> {
+        (0..length(input)?).map(|_| Self::parse_nested(input, depth)).collect()
+    }
+
+    fn obis(&self) -> Option<[u8; 6]> {
+        match self {
+            Self::OctetString(bytes)    => bytes.as_slice().try_into().ok(),
+            _                           => None,
+        }
+    }
+
+    fn scaler_unit(&self) -> Option<(i8, u8)> {
+        match self {
+            Self::Structure(items)  => match items.as_slice() {
+                [Self::Integer(scaler), Self::Integer(unit)]    => Some(((*scaler).try_into().ok()?, (*unit).try_into().ok()?)),
+                _                                               => None,
+            },
+            _                       => None,
+        }
+    }
+
+    /// Scaler of a value sent without scaler and unit. Kamstrup, which numbers electricity registers
+    /// on channel 1, sends currents in 0.01 A and energy in 0.01 kWh; other values are in base units.
+    fn default_scaler(obis: [u8; 6]) -> i8 {
+        match obis {
+            [1, 1, c, 7, _, _] if c % 20 == 11  => -2,
+            [1, 1, 1..=4, 8, _, _]              => 1,
+            _                                   => 0,
+        }
+    }
+
+    /// Find all OBIS codes in a push message, with the value that follows each of them.
+    ///
+    /// Aidon wraps each register in its own structure with a scaler and unit, Kamstrup and most
+    /// Austrian meters send one flat structure of alternating codes and values.
+    pub fn registers(&self) -> Vec<Register> {
+        let mut result = vec![];
+        self.collect_registers(&mut result);
+        result
+    }
+
+    fn collect_registers(&self, result: &mut Vec<Register>) {
+        let items = match self {
+            Self::Array(items) | Self::Structure(items)     => items,
+            _                                               => return,
+        };
+        let mut i = 0;
+        while i < items.len() {
+            match (items[i].obis(), items.get(i + 1)) {
+                (Some(obis), Some(value)) if !matches!(value, Self::Array(_) | Self::Structure(_)) => {
+                    let (scaler, unit) = items.get(i + 2)
+                        .and_then(Self::scaler_unit)
+                        .map_or((Self::default_scaler(obis), None), |(scaler, unit)| (scaler, Some(unit)));
+                    result.push(Register { obis, value: value.clone(), scaler, unit });
+                    i += if unit.is_some() { 3 } else { 2 };
+                },
+                _ => {
+                    items[i].collect_registers(result);
+                    i += 1;
+                },
+            }
+        }
+    }
+}
+
+/// OBIS codes and scalers of the values that Kaifa meters send without codes, by position in the
+/// list. The first three are the list version, meter ID and meter type; currents are in mA and
+/// voltages in 0.1 V.
+fn kaifa_codes(phases: u8, hourly: bool) -> Vec<Option<([u8; 6], i8)>> {
+    let mut codes = vec![None, Some(([0, 0, 96, 1, 1, 255], 0)), None];
+    codes.extend([1, 2, 3, 4].map(|c| Some(([1, 0, c, 7, 0, 255], 0))));
+    codes.extend((1..=phases).map(|phase| Some(([1, 0, 11 + 20 * phase, 7, 0, 255], -3))));
+    codes.extend((1..=phases).map(|phase| Some(([1, 0, 12 + 20 * phase, 7, 0, 255], -1))));
+    if hourly {
+        codes.push(Some(([0, 0, 1, 0, 0, 255], 0)));
+        codes.extend([1, 2, 3, 4].map(|c| Some(([1, 0, c, 8, 0, 255], 0))));
+    }
+    codes
+}
+
+/// Registers of a Kaifa list, recognised by its number of values: only active power every two
+/// seconds, instantaneous values every ten seconds, and meter readings every hour.
+fn kaifa_registers(items: &[Data]) -> Vec<Register> {
+    let codes = match items {
+        [Data::Integer(_)]      => vec![Some(([1, 0, 1, 7, 0, 255], 0))],
+        _                       => match items.len() {
+            9                   => kaifa_codes(1, false),
+            13                  => kaifa_codes(3, false),
+            14                  => kaifa_codes(1, true),
+            18                  => kaifa_codes(3, true),
+            _                   => return vec![],
+        },
+    };
+    items.iter().zip(codes)
+        .filter_map(|(value, code)| code.map(|(obis, scaler)| Register { obis, value: value.clone(), scaler, unit: None }))
+        .collect()
+}
+
+impl Register {
+    // units from IEC 62056-62 that have a DSMR equivalent; meters that send no unit use base units
+    fn unit_str(&self) -> Option<&'static str> {
+        let [a, _, c, d, _, _] = self.obis;
+        let unit = match (self.unit, a, c % 20, d) {
+            (Some(unit), _, _, _)                   => unit,
+            (None, 1, 1 | 2, 7)                     => 27,
+            (None, 1, 3 | 4, 7)                     => 29,
+            (None, 1, 1 | 2, 8)                     => 30,
+            (None, 1, 3 | 4, 8)                     => 32,
+            (None, 1, 11, 7)                        => 33,
+            (None, 1, 12, 7)                        => 35,
+            _                                       => return None,
+        };
+        match unit {
+            7   => Some("s"),
+            13  => Some("m3"),
+            27  => Some("W"),
+            28  => Some("VA"),
+            29  => Some("var"),
+            30  => Some("Wh"),
+            31  => Some("VAh"),
+            32  => Some("varh"),
+            33  => Some("A"),
+            35  => Some("V"),
+            44  => Some("Hz"),
+            _   => None,
+        }
+    }
+
+    fn scale(&self, value: f64) -> f64 {
+        // dividing keeps 2307 * 10^-1 at 230.7
+        match self.scaler {
+            scaler if scaler < 0    => value / 10f64.powi(-i32::from(scaler)),
+            scaler                  => value * 10f64.powi(scaler.into()),
+        }
+    }
+
+    /// Format a COSEM date-time the way DSMR writes timestamps.
+    fn format_datetime(bytes: &[u8]) -> Option<String> {
+        let [year_hi, year_lo, month, day, _, hour, minute, second, _, _, _, status] = *bytes else { return None };
+        let year = u16::from_be_bytes([year_hi, year_lo]) % 100;
+        let dst = if status & 0x80 != 0 { 'S' } else { 'W' };
+        Some(format!("{year:02}{month:02}{day:02}{hour:02}{minute:02}{second:02}{dst}"))
+    }
+
+    /// Render this register as a DSMR line, so the regular attribute parser can decode it.
+    pub fn to_line(&self) -> Option<String> {
+        // Kamstrup numbers electricity registers on channel 1 where DSMR uses 0, and DLMS meters put
+        // their ID at 0-0:96.1.0 where DSMR uses 0-0:96.1.1
+        let [a, b, c, d, e, _] = self.obis;
+        let b = if a == 1 && b == 1 { 0 } else { b };
+        let e = if [a, b, c, d, e] == [0, 0, 96, 1, 0] { 1 } else { e };
+        let obis = format!("{a}-{b}:{c}.{d}.{e}");
+
+        let value = match self.value {
+            Data::Integer(n)                                => format!("{}", self.scale(n as f64)),
+            Data::Float(x)                                  => format!("{}", self.scale(x)),
+            Data::OctetString(ref bytes) if bytes.len() == 12 && [a, c, d, e] == [0, 1, 0, 0]
+                                                            => return Some(format!("{obis}({})", Self::format_datetime(bytes)?)),
+            Data::OctetString(ref bytes)                    => return Some(format!("{obis}({})", bytes.iter().map(|b| format!("{b:02X}")).collect::<String>())),
+            Data::VisibleString(ref text)                   => return Some(format!("{obis}({})", text.bytes().map(|b| format!("{b:02X}")).collect::<String>())),
+            _                                               => return None,
+        };
+        match self.unit_str() {
+            Some(unit)  => Some(format!("{obis}({value}*{unit})")),
+            None        => Some(format!("{obis}({value})")),
+        }
+    }
+}
+
+/// Decode a data-notification APDU into a telegram, decrypting it first if needed.
+pub fn telegram(apdu: &[u8], options: &Options, decryptor: Option<&mut Decryptor>) -> Result<Telegram, anyhow::Error> {
+    let plaintext;
+    let mut input = match apdu.first() {
+        Some(&GENERAL_GLO_CIPHERING)    => {
+            plaintext = decryptor
+                .ok_or_else(|| anyhow!("Received encrypted APDU, but no key was given"))?
+                .decrypt(apdu)?;
+            &plaintext[..]
+        },
+        _                               => apdu,
+    };
+
+    if take_array::<1>(&mut input)?[0] != DATA_NOTIFICATION {
+        return Err(anyhow!("Not a data-notification APDU: {apdu:02x?}"));
+    }
+    let _invoke_id = take(&mut input, 4)?;
+    // the date-time is an octet string, which Kaifa sends with its 0x09 tag and others without
+    if input.first() == Some(&0x09) {
+        take(&mut input, 1)?;
+    }
+    let datetime = match length(&mut input)? {
+        0   => None,
+        n   => Some(take(&mut input, n)?),
+    };
+    let body = Data::parse(&mut input)
+        .context("Error parsing notification body")?;
+
+    // use the list identifier, if any, as header
+    let header = match body {
+        Data::Structure(ref items) | Data::Array(ref items) => items.iter().find_map(|item| match item {
+            Data::VisibleString(text)   => Some(text.clone()),
+            _                           => None,
+        }),
+        _                                                   => None,
+    };
+
+    let mut lines: Vec<String> = datetime.and_then(Register::format_datetime).map(|timestamp| format!("0-0:1.0.0({timestamp})")).into_iter().collect();
+    let registers = match body.registers() {
+        registers if !registers.is_empty()                  => registers,
+        // Kaifa sends the values of each list in a fixed order, without OBIS codes
+        _                                                   => match body {
+            Data::Structure(ref items) | Data::Array(ref items) => kaifa_registers(items),
+            _                                               => vec![],
+        },
+    };
+    lines.extend(registers.iter().filter_map(Register::to_line));
+    Ok(binary::telegram(&header.unwrap_or_default(), lines, options))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attribute::Attribute;
+
+    fn obis(code: [u8; 6]) -> Vec<u8> {
+        [&[0x09, 0x06][..], &code].concat()
+    }
+
+    #[test]
+    fn test_data() -> Result<(), anyhow::Error> {
+        let tests: [(&[u8], Data); 6] = [
+            (&[0x06, 0x00, 0x00, 0x05, 0x8c],                   Data::Integer(1420)),
+            (&[0x10, 0xff, 0xfe],                               Data::Integer(-2)),
+            (&[0x0a, 0x02, 0x41, 0x42],                         Data::VisibleString("AB".into())),
+            (&[0x09, 0x02, 0x01, 0x02],                         Data::OctetString(vec![1, 2])),
+            (&[0x02, 0x02, 0x0f, 0xff, 0x16, 0x1b],             Data::Structure(vec![Data::Integer(-1), Data::Integer(27)])),
+            (&[0x01, 0x00],                                     Data::Array(vec![])),
+        ];
+        for (bytes, expected) in tests {
+            assert_eq!(Data::parse(&mut &bytes[..])?, expected);
+        }
+        for bytes in [&[][..], &[0x06, 0x00], &[0x09, 0x05, 0x00], &[0x01, 0x82, 0xff], &[0x42]] {
+            assert!(Data::parse(&mut &bytes[..]).is_err(), "{bytes:02x?}");
+        }
+        let nested = [0x01, 0x01].repeat(50_000);
+        assert!(Data::parse(&mut &nested[..]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_kamstrup() -> Result<(), anyhow::Error> {
+        let mut apdu = vec![0x0f, 0x00, 0x00, 0x00, 0x00, 0x0c, 0x07, 0xe1, 0x0b, 0x0f, 0x03, 0x0a, 0x1b, 0x00, 0xff, 0x80, 0x00, 0x00];
+        apdu.extend([0x02, 0x09, 0x0a, 0x0e]);
+        apdu.extend(b"Kamstrup_V0001");
+        apdu.extend(obis([1, 1, 0, 0, 5, 255]));
+        apdu.extend([0x0a, 0x04]);
+        apdu.extend(b"5706");
+        apdu.extend(obis([1, 1, 1, 7, 0, 255]));
+        apdu.extend([0x06, 0x00, 0x00, 0x05, 0x8c]);
+        apdu.extend(obis([1, 1, 31, 7, 0, 255]));
+        apdu.extend([0x06, 0x00, 0x00, 0x00, 0x7b]);
+        apdu.extend(obis([1, 1, 1, 8, 0, 255]));
+        apdu.extend([0x06, 0x00, 0x00, 0x30, 0x39]);
+        let telegram = telegram(&apdu, &Options::default(), None)?;
+        assert_eq!(telegram.header.model, "Kamstrup_V0001");
+        assert!(matches!(telegram.elements[0], Attribute::Timestamp(_)));
+        assert_eq!(telegram.elements[2..], [
+            Attribute::ActualPowerDelivered(1.42),
+            Attribute::InstantCurrent(1, 1.23),
+            Attribute::ElectricityDelivered(0, 123.45),
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_aidon() -> Result<(), anyhow::Error> {
+        let mut apdu = vec![0x0f, 0x40, 0x00, 0x00, 0x00, 0x00, 0x01, 0x02];
+        apdu.extend([0x02, 0x03]);
+        apdu.extend(obis([1, 0, 32, 7, 0, 255]));
+        apdu.extend([0x12, 0x09, 0x03, 0x02, 0x02, 0x0f, 0xff, 0x16, 0x23]);
+        apdu.extend([0x02, 0x03]);
+        apdu.extend(obis([1, 0, 31, 7, 0, 255]));
+        apdu.extend([0x10, 0x00, 0x2a, 0x02, 0x02, 0x0f, 0xff, 0x16, 0x21]);
+        let telegram = telegram(&apdu, &Options::default(), None)?;
+        assert_eq!(telegram.elements, vec![Attribute::InstantVoltage(1, 230.7), Attribute::InstantCurrent(1, 4.2)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_kaifa() -> Result<(), anyhow::Error> {
+        let u32 = |n: u32| [&[0x06][..], &n.to_be_bytes()].concat();
+        // Kaifa tags the date-time of the notification, unlike Aidon and Kamstrup
+        let mut apdu = vec![0x0f, 0x40, 0x00, 0x00, 0x00, 0x09, 0x0c, 0x07, 0xe1, 0x09, 0x0e, 0x04, 0x13, 0x1f, 0x14, 0xff, 0x80, 0x00, 0x00];
+        apdu.extend([0x02, 0x12]);
+        apdu.extend([0x0a, 0x07]);
+        apdu.extend(b"KFM_001");
+        apdu.extend([0x0a, 0x04]);
+        apdu.extend(b"6970");
+        apdu.extend([0x0a, 0x08]);
+        apdu.extend(b"MA304H3E");
+        for n in [1420, 0, 0, 120, 4200, 2000, 1000, 2307, 2310, 2295] {
+            apdu.extend(u32(n));
+        }
+        apdu.extend([0x09, 0x0c, 0x07, 0xe1, 0x0b, 0x0f, 0x03, 0x0a, 0x00, 0x00, 0xff, 0x80, 0x00, 0x00]);
+        for n in [12345678, 0, 1000, 2000] {
+            apdu.extend(u32(n));
+        }
+        let list = telegram(&apdu, &Options::default(), None)?;
+        assert_eq!(list.header.model, "KFM_001");
+        assert!(matches!(list.elements[0], Attribute::Timestamp(_)));
+        assert_eq!(list.elements[1..5], [
+            Attribute::EquipmentIdentifier("6970".into()),
+            Attribute::ActualPowerDelivered(1.42),
+            Attribute::ActualPowerReceived(0.0),
+            Attribute::ActualReactivePowerDelivered(0.0),
+        ]);
+        assert!(list.elements.contains(&Attribute::InstantCurrent(1, 4.2)));
+        assert!(list.elements.contains(&Attribute::InstantVoltage(3, 229.5)));
+        assert!(list.elements.contains(&Attribute::ElectricityDelivered(0, 12345.678)));
+
+        // list 1, as pushed every two seconds
+        let apdu = [
+            0x0f, 0x40, 0x00, 0x00, 0x00, 0x09, 0x0c, 0x07, 0xe1, 0x09, 0x0e, 0x04, 0x13, 0x1f, 0x02, 0xff, 0x80, 0x00, 0x00,
+            0x02, 0x01, 0x06, 0x00, 0x00, 0x16, 0x5d,
+        ];
+        let list = telegram(&apdu, &Options::default(), None)?;
+        assert_eq!(list.elements[1..], [Attribute::ActualPowerDelivered(5.725)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_encrypted_without_key() {
+        assert!(telegram(&[0xdb, 0x08], &Options::default(), None).is_err());
+        assert!(telegram(&[0x0e, 0x00], &Options::default(), None).is_err());
+    }
+}
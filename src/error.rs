@@ -0,0 +1,97 @@
+use std::io::ErrorKind;
+
+/// Errors reading and parsing DSMR telegrams.
+#[derive(Debug, thiserror::Error)]
+pub enum DsmrError {
+    /// The checksum after `!` does not match the telegram.
+    #[error("CRC mismatch: expected {expected:04X}, got {actual:?}")]
+    CrcMismatch { expected: u16, actual: String },
+
+    /// The part before the first parenthesis is not an OBIS code.
+    #[error("Invalid OBIS code {obis:?} in line {line}: {raw:?}")]
+    InvalidObis { line: usize, obis: String, raw: String },
+
+    /// A value that cannot be parsed, like a bad number, unit or timestamp.
+    #[error("Bad value for {obis} in line {line}: {raw:?}")]
+    BadValue { line: usize, obis: String, raw: String, #[source] source: Box<dyn std::error::Error + Send + Sync> },
+
+    /// The checksum of a binary frame, like the HDLC FCS, SML CRC or IEC 62056-21 BCC, does not match.
+    #[error("{name} mismatch: expected {expected:04X}, got {actual:04X}")]
+    ChecksumMismatch { name: &'static str, expected: u16, actual: u16 },
+
+    /// A frame that cannot be decoded or authenticated, like one with a bad length or GCM tag.
+    #[error("Bad {kind} frame")]
+    BadFrame { kind: &'static str, #[source] source: Box<dyn std::error::Error + Send + Sync> },
+
+    /// The meter did not answer an IEC 62056-21 request in time.
+    #[error("No response from meter")]
+    Timeout,
+
+    /// Reading failed too many times in a row, like when the baud rate is wrong.
+    #[error("Giving up after {count} consecutive failures")]
+    TooManyFailures { count: u32 },
+
+    #[error("Unexpected EOF reached")]
+    Eof,
+
+    #[error("I/O error")]
+    Io(#[from] std::io::Error),
+}
+
+impl DsmrError {
+    /// Set the line number, counted from the header line, of errors about a single line.
+    pub fn at_line(mut self, number: usize) -> Self {
+        if let Self::InvalidObis { ref mut line, .. } | Self::BadValue { ref mut line, .. } = self {
+            *line = number;
+        }
+        self
+    }
+
+    /// Whether this error points at the connection to the meter, like a wrong baud rate or a bad
+    /// cable, rather than at a single value the meter sent.
+    pub fn is_link_failure(&self) -> bool {
+        matches!(self, Self::CrcMismatch { .. } | Self::ChecksumMismatch { .. } | Self::BadFrame { .. } | Self::Timeout)
+    }
+
+    /// Whether reading can go on with the next telegram after this error.
+    pub fn is_recoverable(&self) -> bool {
+        !matches!(self, Self::TooManyFailures { .. } | Self::Eof | Self::Io(_))
+    }
+
+    /// Whether the source ran out of data.
+    pub fn is_eof(&self) -> bool {
+        match self {
+            Self::Eof       => true,
+            Self::Io(e)     => e.kind() == ErrorKind::UnexpectedEof,
+            _               => false,
+        }
+    }
+}
+
+/// Whether an error from one of the decoders means the source ran out of data.
+pub fn is_eof(e: &anyhow::Error) -> bool {
+    e.chain().any(|cause| {
+        matches!(cause.downcast_ref::<DsmrError>(), Some(DsmrError::Eof))
+            || cause.downcast_ref::<std::io::Error>().is_some_and(|e| e.kind() == ErrorKind::UnexpectedEof)
+    })
+}
+
+/// Whether an error from one of the decoders came from the source itself, rather than its data.
+pub fn is_io(e: &anyhow::Error) -> bool {
+    e.chain().any(|cause| cause.is::<std::io::Error>())
+}
+
+/// Turn an error from decoding a `kind` frame into a [`DsmrError`]: typed errors and I/O errors are
+/// kept, running out of data becomes [`DsmrError::Eof`], and anything else [`DsmrError::BadFrame`],
+/// so reading can go on with the next frame.
+pub fn bad_frame(kind: &'static str, e: anyhow::Error) -> DsmrError {
+    let e = match e.downcast::<DsmrError>() {
+        Ok(e)   => return e,
+        Err(e)  => e,
+    };
+    match e.downcast::<std::io::Error>() {
+        Ok(e) if e.kind() == ErrorKind::UnexpectedEof   => DsmrError::Eof,
+        Ok(e)                                           => DsmrError::Io(e),
+        Err(e)                                          => DsmrError::BadFrame { kind, source: e.into() },
+    }
+}
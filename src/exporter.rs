@@ -1,17 +1,63 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use chrono::{DateTime, FixedOffset};
 use lazy_static::lazy_static;
 use prometheus_exporter;
 use prometheus_exporter::prometheus;
-use anyhow::Context;
+use anyhow::{anyhow, Context};
+use log::info;
+#[cfg(feature = "async")]
+use log::{debug, warn};
+
+use crate::attribute::{Attribute, MonthlyPeak, PowerFailureEvent};
+use crate::telegram::{Statistics, Telegram};
+
+const MBUS_LABELS: &[&str] = &["channel", "device_type", "equipment_id"];
+const TARIFF_LABELS: &[&str] = &["tariff", "tariff_name"];
+
+/// Human names for tariff numbers, exported as the `tariff_name` label.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TariffNames(HashMap<u8, String>);
+
+impl FromStr for TariffNames {
+    type Err = anyhow::Error;
+
+    /// Parse a list like `1=low,2=normal`.
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut names = HashMap::new();
+        for entry in text.split(',').filter(|entry| !entry.is_empty()) {
+            let (tariff, name) = entry
+                .split_once('=')
+                .ok_or_else(|| anyhow!("Cannot find '=' in tariff name {entry:?}"))?;
+            let tariff = tariff.trim().parse()
+                .with_context(|| format!("Error parsing tariff number {tariff:?}"))?;
+            names.insert(tariff, name.trim().to_owned());
+        }
+        Ok(TariffNames(names))
+    }
+}
 
-use crate::attribute::Attribute;
+impl TariffNames {
+    fn get(&self, tariff: u8) -> &str {
+        self.0.get(&tariff).map(String::as_str).unwrap_or_default()
+    }
+}
 
 // TODO: move this to own type instead of lazy_statics
 lazy_static! {
     static ref ELECTRICITY_DELIVERED: prometheus::GaugeVec
-        = prometheus::register_gauge_vec!("electricity_delivered", "Meter reading electricity delivered to client (kWh)", &["tariff"]).unwrap();
+        = prometheus::register_gauge_vec!("electricity_delivered", "Meter reading electricity delivered to client (kWh)", TARIFF_LABELS).unwrap();
 
     static ref ELECTRICITY_RECEIVED: prometheus::GaugeVec
-        = prometheus::register_gauge_vec!("electricity_received", "Meter reading electricity delivered by client (kWh)", &["tariff"]).unwrap();
+        = prometheus::register_gauge_vec!("electricity_received", "Meter reading electricity delivered by client (kWh)", TARIFF_LABELS).unwrap();
+
+    static ref REACTIVE_ENERGY_DELIVERED: prometheus::GaugeVec
+        = prometheus::register_gauge_vec!("reactive_energy_delivered", "Meter reading reactive energy delivered to client (+Q) (kvarh)", TARIFF_LABELS).unwrap();
+
+    static ref REACTIVE_ENERGY_RECEIVED: prometheus::GaugeVec
+        = prometheus::register_gauge_vec!("reactive_energy_received", "Meter reading reactive energy delivered by client (-Q) (kvarh)", TARIFF_LABELS).unwrap();
 
     static ref TARIFF_INDICATOR: prometheus::IntGauge
         = prometheus::register_int_gauge!("tariff_indicator", "Tariff indicator electricity").unwrap();
@@ -22,6 +68,36 @@ lazy_static! {
     static ref ACTUAL_POWER_RECEIVED: prometheus::Gauge
         = prometheus::register_gauge!("actual_power_received", "Actual electricity power received (-P) (kW)").unwrap();
 
+    static ref ACTUAL_REACTIVE_POWER_DELIVERED: prometheus::Gauge
+        = prometheus::register_gauge!("actual_reactive_power_delivered", "Actual reactive power delivered (+Q) (kvar)").unwrap();
+
+    static ref ACTUAL_REACTIVE_POWER_RECEIVED: prometheus::Gauge
+        = prometheus::register_gauge!("actual_reactive_power_received", "Actual reactive power received (-Q) (kvar)").unwrap();
+
+    static ref CURRENT_AVERAGE_DEMAND: prometheus::Gauge
+        = prometheus::register_gauge!("current_average_demand", "Average demand in the current quarter-hour (kW)").unwrap();
+
+    static ref PEAK_DEMAND: prometheus::Gauge
+        = prometheus::register_gauge!("peak_demand", "Peak quarter-hourly average demand in the current month (kW)").unwrap();
+
+    static ref PEAK_DEMAND_TIMESTAMP: prometheus::Gauge
+        = prometheus::register_gauge!("peak_demand_timestamp", "Time of the peak demand in the current month (Unix time)").unwrap();
+
+    static ref PEAK_DEMAND_HISTORY: prometheus::GaugeVec
+        = prometheus::register_gauge_vec!("peak_demand_history", "Peak quarter-hourly average demand by past month (kW)", &["month"]).unwrap();
+
+    static ref BREAKER_STATE: prometheus::IntGauge
+        = prometheus::register_int_gauge!("breaker_state", "Breaker state (0 = disconnected, 1 = connected, 2 = ready for reconnection)").unwrap();
+
+    static ref LIMITER_THRESHOLD: prometheus::Gauge
+        = prometheus::register_gauge!("limiter_threshold", "Power limiter threshold (kW)").unwrap();
+
+    static ref LIMITER_THRESHOLD_CURRENT: prometheus::Gauge
+        = prometheus::register_gauge!("limiter_threshold_current", "Current limiter threshold (A)").unwrap();
+
+    static ref FUSE_THRESHOLD: prometheus::GaugeVec
+        = prometheus::register_gauge_vec!("fuse_threshold", "Fuse supervision threshold by phase (A)", &["phase"]).unwrap();
+
     static ref INSTANT_VOLTAGE: prometheus::GaugeVec
         = prometheus::register_gauge_vec!("instant_voltage", "Instantaneous voltage by phase (V)", &["phase"]).unwrap();
 
@@ -34,8 +110,80 @@ lazy_static! {
     static ref INSTANT_POWER_RECEIVED: prometheus::GaugeVec
         = prometheus::register_gauge_vec!("instant_power_received", "Instantaneous active power received by phase (kW)", &["phase"]).unwrap();
 
-    static ref GAS_DELIVERED: prometheus::Gauge
-        = prometheus::register_gauge!("gas_delivered", "Gas delivered to client (m³)").unwrap();
+    static ref INSTANT_REACTIVE_POWER_DELIVERED: prometheus::GaugeVec
+        = prometheus::register_gauge_vec!("instant_reactive_power_delivered", "Instantaneous reactive power delivered by phase (kvar)", &["phase"]).unwrap();
+
+    static ref INSTANT_REACTIVE_POWER_RECEIVED: prometheus::GaugeVec
+        = prometheus::register_gauge_vec!("instant_reactive_power_received", "Instantaneous reactive power received by phase (kvar)", &["phase"]).unwrap();
+
+    static ref GAS_DELIVERED: prometheus::GaugeVec
+        = prometheus::register_gauge_vec!("gas_delivered", "Gas delivered to client by M-Bus channel (m³)", MBUS_LABELS).unwrap();
+
+    static ref GAS_DELIVERED_UNCORRECTED: prometheus::GaugeVec
+        = prometheus::register_gauge_vec!("gas_delivered_uncorrected", "Gas delivered to client by M-Bus channel, not temperature corrected (m³)", MBUS_LABELS).unwrap();
+
+    static ref HEAT_DELIVERED: prometheus::GaugeVec
+        = prometheus::register_gauge_vec!("heat_delivered", "Heat delivered to client by M-Bus channel (GJ)", MBUS_LABELS).unwrap();
+
+    static ref COLD_DELIVERED: prometheus::GaugeVec
+        = prometheus::register_gauge_vec!("cold_delivered", "Cold delivered to client by M-Bus channel (GJ)", MBUS_LABELS).unwrap();
+
+    static ref WATER_DELIVERED: prometheus::GaugeVec
+        = prometheus::register_gauge_vec!("water_delivered", "Water delivered to client by M-Bus channel (m³)", MBUS_LABELS).unwrap();
+
+    static ref THERMAL_VOLUME: prometheus::GaugeVec
+        = prometheus::register_gauge_vec!("thermal_volume", "Water through a heat or cold meter by M-Bus channel (m³)", MBUS_LABELS).unwrap();
+
+    static ref MBUS_READING_TIMESTAMP: prometheus::GaugeVec
+        = prometheus::register_gauge_vec!("mbus_reading_timestamp", "Time of the last reading by M-Bus channel (Unix time)", MBUS_LABELS).unwrap();
+
+    static ref LAST_POWER_FAILURE_TIMESTAMP: prometheus::Gauge
+        = prometheus::register_gauge!("last_power_failure_timestamp", "End of the last logged power failure (Unix time)").unwrap();
+
+    static ref LAST_POWER_FAILURE_DURATION: prometheus::Gauge
+        = prometheus::register_gauge!("last_power_failure_duration", "Duration of the last logged power failure (s)").unwrap();
+
+    static ref POWER_FAILURE_DURATION: prometheus::Histogram
+        = prometheus::register_histogram!("power_failure_duration", "Durations of logged power failures (s)",
+                                          vec![1.0, 10.0, 60.0, 180.0, 600.0, 1800.0, 3600.0, 4.0 * 3600.0, 24.0 * 3600.0]).unwrap();
+
+    static ref METER_INFO: prometheus::GaugeVec
+        = prometheus::register_gauge_vec!("dsmr_meter_info", "Meter manufacturer, model, DSMR version, equipment identifier and firmware version information",
+                                          &["manufacturer", "model", "version", "equipment_id", "version_information"]).unwrap();
+
+    static ref TEXT_MESSAGE: prometheus::GaugeVec
+        = prometheus::register_gauge_vec!("text_message_info", "Text message from the grid operator", &["message"]).unwrap();
+
+    static ref NUMERIC_MESSAGE: prometheus::GaugeVec
+        = prometheus::register_gauge_vec!("numeric_message_info", "Numeric message code from the grid operator", &["message"]).unwrap();
+
+    static ref TELEGRAMS_ACCEPTED: prometheus::IntCounter
+        = prometheus::register_int_counter!("dsmr_telegrams_accepted_total", "Telegrams read and parsed successfully").unwrap();
+
+    static ref CRC_FAILURES: prometheus::IntCounter
+        = prometheus::register_int_counter!("dsmr_crc_failures_total", "Telegrams rejected because of a CRC mismatch").unwrap();
+
+    static ref RESYNCS: prometheus::IntCounter
+        = prometheus::register_int_counter!("dsmr_resyncs_total", "Times the reader skipped bytes to find the start of a telegram").unwrap();
+
+    static ref BYTES_DISCARDED: prometheus::IntCounter
+        = prometheus::register_int_counter!("dsmr_bytes_discarded_total", "Bytes skipped outside of complete telegrams").unwrap();
+
+    static ref BAD_FRAMES: prometheus::IntCounter
+        = prometheus::register_int_counter!("dsmr_bad_frames_total", "Binary frames that could not be decoded or authenticated").unwrap();
+
+    static ref BAD_VALUES: prometheus::IntCounter
+        = prometheus::register_int_counter!("dsmr_bad_values_total", "Lines with an invalid OBIS code or a value that could not be parsed").unwrap();
+
+    static ref POWER_FAILURE_SEEN: Mutex<Option<DateTime<FixedOffset>>> = Mutex::new(None);
+
+    static ref METER_INFO_SEEN: Mutex<Vec<Vec<String>>> = Mutex::new(vec![]);
+
+    static ref PEAK_DEMAND_HISTORY_SEEN: Mutex<Vec<String>> = Mutex::new(vec![]);
+
+    static ref TEXT_MESSAGE_SEEN: Mutex<Option<String>> = Mutex::new(None);
+
+    static ref NUMERIC_MESSAGE_SEEN: Mutex<Option<String>> = Mutex::new(None);
 }
 
 pub fn start(listen: &str) -> Result<(), anyhow::Error> {
@@ -46,20 +194,333 @@ pub fn start(listen: &str) -> Result<(), anyhow::Error> {
     Ok(())
 }
 
-pub fn export(attributes: &[Attribute]) {
+/// Serve the metrics on the tokio runtime, like [`start`] does on a thread of its own.
+#[cfg(feature = "async")]
+pub async fn serve(listen: String) -> Result<(), anyhow::Error> {
+    let listener = tokio::net::TcpListener::bind(&listen).await
+        .with_context(|| format!("Error starting Prometheus exporter on {listen:?}"))?;
+    loop {
+        match listener.accept().await {
+            Ok((socket, _)) => { tokio::spawn(respond(socket)); },
+            Err(e)          => warn!("Error accepting Prometheus connection: {e}"),
+        }
+    }
+}
+
+/// Answer one HTTP request with the metrics, on `/metrics` only like `prometheus_exporter`.
+#[cfg(feature = "async")]
+async fn respond(mut socket: tokio::net::TcpStream) {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use prometheus::Encoder;
+
+    // the request line is all that matters, and it fits in the first read of any real client
+    let mut request = vec![0; 8 * 1024];
+    let Ok(n) = socket.read(&mut request).await else { return };
+    let path = std::str::from_utf8(&request[..n]).ok()
+        .and_then(|request| request.split_whitespace().nth(1))
+        .unwrap_or_default();
+
+    let encoder = prometheus::TextEncoder::new();
+    let (status, content_type, body) = match path {
+        "/metrics"  => {
+            let mut body = vec![];
+            if let Err(e) = encoder.encode(&prometheus::gather(), &mut body) {
+                warn!("Error encoding metrics: {e}");
+                return;
+            }
+            ("200 OK", encoder.format_type(), body)
+        },
+        _           => ("404 Not Found", "text/plain", b"try /metrics for metrics\n".to_vec()),
+    };
+    let head = format!("HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", body.len());
+    if let Err(e) = socket.write_all(&[head.as_bytes(), &body].concat()).await {
+        debug!("Error answering Prometheus request: {e}");
+    }
+}
+
+fn export_power_failures(events: &[PowerFailureEvent]) {
+    // the meter repeats its whole log in every telegram, so only observe events newer than seen before
+    let mut seen = POWER_FAILURE_SEEN.lock().unwrap_or_else(|e| e.into_inner());
+    for event in events.iter().filter(|event| seen.is_none_or(|last| event.ended_at > last)) {
+        POWER_FAILURE_DURATION.observe(event.duration.as_secs_f64());
+    }
+    if let Some(last) = events.iter().max_by_key(|event| event.ended_at) {
+        LAST_POWER_FAILURE_TIMESTAMP.set(last.ended_at.timestamp() as f64);
+        LAST_POWER_FAILURE_DURATION.set(last.duration.as_secs_f64());
+        *seen = seen.max(Some(last.ended_at));
+    }
+}
+
+fn export_message(gauge: &prometheus::GaugeVec, seen: &Mutex<Option<String>>, kind: &str, message: &str) {
+    // keep a single series with the current message, and log every change
+    let mut seen = seen.lock().unwrap_or_else(|e| e.into_inner());
+    if seen.as_deref() == Some(message) {
+        return;
+    }
+    if seen.is_some() || !message.is_empty() {
+        info!("{kind} message changed to {message:?}");
+    }
+    gauge.reset();
+    gauge.with_label_values(&[message]).set(1.0);
+    *seen = Some(message.to_owned());
+}
+
+fn export_peak_demand_history(peaks: &[MonthlyPeak]) {
+    // drop the months that fell out of the meter's window, but only when the window moved
+    let months: Vec<String> = peaks.iter().map(|peak| peak.month.format("%Y-%m").to_string()).collect();
+    let mut seen = PEAK_DEMAND_HISTORY_SEEN.lock().unwrap_or_else(|e| e.into_inner());
+    if *seen != months {
+        PEAK_DEMAND_HISTORY.reset();
+    }
+    for (month, peak) in months.iter().zip(peaks) {
+        PEAK_DEMAND_HISTORY.with_label_values(&[month]).set(peak.demand);
+    }
+    *seen = months;
+}
+
+fn export_meter_info(telegram: &Telegram) {
+    let header = &telegram.header;
+    let mut labels = vec![
+        header.manufacturer_name().map(str::to_owned).unwrap_or_else(|| header.manufacturer.clone()),
+        header.model.clone(),
+        String::new(),
+        String::new(),
+        String::new(),
+    ];
+    for attr in &telegram.elements {
+        match *attr {
+            Attribute::Version(ref version)                     => labels[2] = version.clone(),
+            Attribute::EquipmentIdentifier(ref id)              => labels[3] = id.clone(),
+            Attribute::VersionInformation(ref info)             => labels[4] = info.clone(),
+            _                                                   => (),
+        }
+    }
+
+    if labels.iter().all(String::is_empty) {
+        return;
+    }
+
+    // keep a series per meter, as several sources may be read at once, and replace it only when
+    // something changed, so scrapes never miss it
+    let same_meter = |seen: &Vec<String>| [0, 1, 3].iter().all(|&i| seen[i] == labels[i]);
+    let mut seen = METER_INFO_SEEN.lock().unwrap_or_else(|e| e.into_inner());
+    match seen.iter_mut().find(|seen| same_meter(seen)) {
+        Some(old) if *old == labels => (),
+        Some(old)                   => {
+            let _ = METER_INFO.remove_label_values(&old.iter().map(String::as_str).collect::<Vec<_>>());
+            METER_INFO.with_label_values(&labels.iter().map(String::as_str).collect::<Vec<_>>()).set(1.0);
+            *old = labels;
+        },
+        None                        => {
+            METER_INFO.with_label_values(&labels.iter().map(String::as_str).collect::<Vec<_>>()).set(1.0);
+            seen.push(labels);
+        },
+    }
+}
+
+/// Add the problems found while reading a telegram to the counters.
+pub fn export_statistics(statistics: &Statistics) {
+    CRC_FAILURES.inc_by(statistics.crc_failures);
+    RESYNCS.inc_by(statistics.resyncs);
+    BYTES_DISCARDED.inc_by(statistics.bytes_discarded);
+    BAD_FRAMES.inc_by(statistics.bad_frames);
+    BAD_VALUES.inc_by(statistics.bad_values);
+}
+
+pub fn export(telegram: &Telegram, tariff_names: &TariffNames) {
+    TELEGRAMS_ACCEPTED.inc();
+    export_meter_info(telegram);
+
+    let attributes = &telegram.elements;
+    let device_types: HashMap<u8, String> = attributes.iter()
+        .filter_map(|attr| match *attr {
+            Attribute::GasEquipmentDeviceType(n, device_type)   => Some((n, device_type.to_string())),
+            _                                                   => None,
+        })
+        .collect();
+    let equipment_ids: HashMap<u8, &str> = attributes.iter()
+        .filter_map(|attr| match *attr {
+            Attribute::GasEquipmentIdentifier(n, ref id)        => Some((n, id.as_str())),
+            _                                                   => None,
+        })
+        .collect();
+    let mbus = |gauge: &prometheus::GaugeVec, n: u8| gauge.with_label_values(&[
+        &n.to_string(),
+        device_types.get(&n).map(String::as_str).unwrap_or_default(),
+        equipment_ids.get(&n).copied().unwrap_or_default(),
+    ]);
+    let tariff = |gauge: &prometheus::GaugeVec, n: u8| gauge.with_label_values(&[&n.to_string(), tariff_names.get(n)]);
+    let mbus_reading = |gauge: &prometheus::GaugeVec, n: u8, timestamp: &DateTime<FixedOffset>, value: f64| {
+        mbus(gauge, n).set(value);
+        mbus(&MBUS_READING_TIMESTAMP, n).set(timestamp.timestamp() as f64);
+    };
+
     for attr in attributes {
         match *attr {
-            Attribute::ElectricityDelivered(tariff, kwh)        => ELECTRICITY_DELIVERED.with_label_values(&[&tariff.to_string()]).set(kwh),
-            Attribute::ElectricityReceived(tariff, kwh)         => ELECTRICITY_RECEIVED.with_label_values(&[&tariff.to_string()]).set(kwh),
+            Attribute::ElectricityDelivered(n, kwh)             => tariff(&ELECTRICITY_DELIVERED, n).set(kwh),
+            Attribute::ElectricityReceived(n, kwh)              => tariff(&ELECTRICITY_RECEIVED, n).set(kwh),
+            Attribute::ReactiveEnergyDelivered(n, kvarh)        => tariff(&REACTIVE_ENERGY_DELIVERED, n).set(kvarh),
+            Attribute::ReactiveEnergyReceived(n, kvarh)         => tariff(&REACTIVE_ENERGY_RECEIVED, n).set(kvarh),
             Attribute::TariffIndicator(tariff)                  => TARIFF_INDICATOR.set(tariff),
             Attribute::ActualPowerDelivered(kw)                 => ACTUAL_POWER_DELIVERED.set(kw),
             Attribute::ActualPowerReceived(kw)                  => ACTUAL_POWER_RECEIVED.set(kw),
+            Attribute::ActualReactivePowerDelivered(kvar)       => ACTUAL_REACTIVE_POWER_DELIVERED.set(kvar),
+            Attribute::ActualReactivePowerReceived(kvar)        => ACTUAL_REACTIVE_POWER_RECEIVED.set(kvar),
+            Attribute::CurrentAverageDemand(kw)                 => CURRENT_AVERAGE_DEMAND.set(kw),
+            Attribute::PeakDemand(ref t, kw)                    => { PEAK_DEMAND.set(kw); PEAK_DEMAND_TIMESTAMP.set(t.timestamp() as f64) },
+            Attribute::PeakDemandHistory(ref peaks)             => export_peak_demand_history(peaks),
+            Attribute::BreakerState(state)                      => BREAKER_STATE.set(state.into()),
+            Attribute::LimiterThreshold(kw)                     => LIMITER_THRESHOLD.set(kw),
+            Attribute::LimiterThresholdCurrent(a)               => LIMITER_THRESHOLD_CURRENT.set(a),
+            Attribute::FuseThreshold(phase, a)                  => FUSE_THRESHOLD.with_label_values(&[&phase.to_string()]).set(a),
             Attribute::InstantVoltage(phase, v)                 => INSTANT_VOLTAGE.with_label_values(&[&phase.to_string()]).set(v),
             Attribute::InstantCurrent(phase, a)                 => INSTANT_CURRENT.with_label_values(&[&phase.to_string()]).set(a),
             Attribute::InstantPowerDelivered(phase, kw)         => INSTANT_POWER_DELIVERED.with_label_values(&[&phase.to_string()]).set(kw),
             Attribute::InstantPowerReceived(phase, kw)          => INSTANT_POWER_RECEIVED.with_label_values(&[&phase.to_string()]).set(kw),
-            Attribute::GasDelivered(_, _, m3)                   => GAS_DELIVERED.set(m3),
+            Attribute::InstantReactivePowerDelivered(phase, kvar) => INSTANT_REACTIVE_POWER_DELIVERED.with_label_values(&[&phase.to_string()]).set(kvar),
+            Attribute::InstantReactivePowerReceived(phase, kvar) => INSTANT_REACTIVE_POWER_RECEIVED.with_label_values(&[&phase.to_string()]).set(kvar),
+            Attribute::GasDelivered(n, ref t, m3)               => mbus_reading(&GAS_DELIVERED, n, t, m3),
+            Attribute::GasDeliveredUncorrected(n, ref t, m3)    => mbus_reading(&GAS_DELIVERED_UNCORRECTED, n, t, m3),
+            Attribute::HeatDelivered(n, ref t, gj)              => mbus_reading(&HEAT_DELIVERED, n, t, gj),
+            Attribute::ColdDelivered(n, ref t, gj)              => mbus_reading(&COLD_DELIVERED, n, t, gj),
+            Attribute::WaterDelivered(n, ref t, m3)             => mbus_reading(&WATER_DELIVERED, n, t, m3),
+            Attribute::ThermalVolume(n, ref t, m3)              => mbus_reading(&THERMAL_VOLUME, n, t, m3),
+            Attribute::PowerFailureLog(ref events)              => export_power_failures(events),
+            Attribute::TextMessage(ref text)                    => export_message(&TEXT_MESSAGE, &TEXT_MESSAGE_SEEN, "Text", text),
+            Attribute::NumericMessage(ref code)                 => export_message(&NUMERIC_MESSAGE, &NUMERIC_MESSAGE_SEEN, "Numeric", code),
             _                                                   => ()
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bytes::Bytes;
+    use chrono::{FixedOffset, TimeZone};
+
+    use super::*;
+    use crate::telegram::Header;
+
+    fn telegram(elements: Vec<Attribute>) -> Telegram {
+        Telegram { header: Header::default(), elements, raw: Bytes::new(), errors: vec![] }
+    }
+
+    #[test]
+    fn test_power_failures_counted_once() {
+        let cet = FixedOffset::east_opt(3600).unwrap();
+        let first = PowerFailureEvent { ended_at: cet.with_ymd_and_hms(2018, 2, 28, 8, 46, 5).unwrap(), duration: Duration::from_secs(486) };
+        let second = PowerFailureEvent { ended_at: cet.with_ymd_and_hms(2019, 3, 1, 12, 0, 0).unwrap(), duration: Duration::from_secs(20) };
+
+        export(&telegram(vec![Attribute::PowerFailureLog(vec![first.clone()])]), &TariffNames::default());
+        export(&telegram(vec![Attribute::PowerFailureLog(vec![first.clone()])]), &TariffNames::default());
+        assert_eq!(POWER_FAILURE_DURATION.get_sample_count(), 1);
+
+        export(&telegram(vec![Attribute::PowerFailureLog(vec![first, second])]), &TariffNames::default());
+        assert_eq!(POWER_FAILURE_DURATION.get_sample_count(), 2);
+        assert_eq!(LAST_POWER_FAILURE_DURATION.get(), 20.0);
+    }
+
+    #[test]
+    fn test_mbus_channels() {
+        let cest = FixedOffset::east_opt(7200).unwrap();
+        let timestamp = cest.with_ymd_and_hms(2022, 6, 11, 16, 25, 10).unwrap();
+        export(&telegram(vec![
+            Attribute::GasEquipmentDeviceType(1, 3),
+            Attribute::GasEquipmentIdentifier(1, "G0123456789012345".into()),
+            Attribute::GasDelivered(1, timestamp, 3814.705),
+            Attribute::GasEquipmentDeviceType(2, 3),
+            Attribute::GasEquipmentIdentifier(2, "G5432109876543210".into()),
+            Attribute::GasDelivered(2, timestamp, 12.5),
+        ]), &TariffNames::default());
+        assert_eq!(GAS_DELIVERED.with_label_values(&["1", "3", "G0123456789012345"]).get(), 3814.705);
+        assert_eq!(GAS_DELIVERED.with_label_values(&["2", "3", "G5432109876543210"]).get(), 12.5);
+        assert_eq!(MBUS_READING_TIMESTAMP.with_label_values(&["1", "3", "G0123456789012345"]).get(), timestamp.timestamp() as f64);
+    }
+
+    #[test]
+    fn test_tariff_names() -> Result<(), anyhow::Error> {
+        let names: TariffNames = "1=low, 2=normal,3=peak".parse()?;
+        export(&telegram(vec![Attribute::ElectricityDelivered(3, 12.5), Attribute::ElectricityDelivered(4, 1.0)]), &names);
+        assert_eq!(ELECTRICITY_DELIVERED.with_label_values(&["3", "peak"]).get(), 12.5);
+        assert_eq!(ELECTRICITY_DELIVERED.with_label_values(&["4", ""]).get(), 1.0);
+        assert!("1:low".parse::<TariffNames>().is_err());
+        assert!("x=low".parse::<TariffNames>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_text_message() {
+        export(&telegram(vec![Attribute::TextMessage("".into())]), &TariffNames::default());
+        assert_eq!(TEXT_MESSAGE.with_label_values(&[""]).get(), 1.0);
+        export(&telegram(vec![Attribute::TextMessage("Maintenance tonight".into())]), &TariffNames::default());
+        assert_eq!(TEXT_MESSAGE.with_label_values(&["Maintenance tonight"]).get(), 1.0);
+        assert!(TEXT_MESSAGE.remove_label_values(&[""]).is_err());
+    }
+
+    #[test]
+    fn test_peak_demand_history() {
+        let cet = FixedOffset::east_opt(3600).unwrap();
+        let peak = |month, demand| MonthlyPeak { month: cet.with_ymd_and_hms(2023, month, 1, 0, 0, 0).unwrap(), timestamp: None, demand };
+        export(&telegram(vec![Attribute::PeakDemandHistory(vec![peak(1, 3.5), peak(2, 4.0)])]), &TariffNames::default());
+        export(&telegram(vec![Attribute::PeakDemandHistory(vec![peak(2, 4.0), peak(3, 2.5)])]), &TariffNames::default());
+        assert_eq!(PEAK_DEMAND_HISTORY.with_label_values(&["2023-03"]).get(), 2.5);
+        assert!(PEAK_DEMAND_HISTORY.remove_label_values(&["2023-01"]).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn test_serve() -> Result<(), anyhow::Error> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let get = |listener: &tokio::net::TcpListener, path: &str| {
+            let (addr, request) = (listener.local_addr(), format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n"));
+            async move {
+                let mut client = tokio::net::TcpStream::connect(addr?).await?;
+                client.write_all(request.as_bytes()).await?;
+                let mut response = String::new();
+                client.read_to_string(&mut response).await?;
+                Ok::<_, anyhow::Error>(response)
+            }
+        };
+
+        export_statistics(&Statistics { crc_failures: 1, ..Statistics::default() });
+        tokio::runtime::Builder::new_current_thread().enable_all().build()?.block_on(async {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+            for (path, status) in [("/metrics", "200 OK"), ("/", "404 Not Found")] {
+                let client = tokio::spawn(get(&listener, path));
+                respond(listener.accept().await?.0).await;
+                let response = client.await??;
+                assert!(response.starts_with(&format!("HTTP/1.1 {status}\r\n")), "{response}");
+                assert_eq!(response.contains("dsmr_crc_failures_total"), path == "/metrics");
+            }
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_meter_info() {
+        let telegram = Telegram {
+            header: Header::parse("ISK5\\2M550E-1012"),
+            elements: vec![
+                Attribute::Version("50".into()),
+                Attribute::EquipmentIdentifier("E0123456789012345".into()),
+                Attribute::VersionInformation("50217".into()),
+            ],
+            raw: Bytes::new(),
+            errors: vec![],
+        };
+        export(&telegram, &TariffNames::default());
+        assert_eq!(METER_INFO.with_label_values(&["Iskra", "M550E-1012", "50", "E0123456789012345", "50217"]).get(), 1.0);
+
+        // another meter gets a series of its own, and a firmware update replaces the old one
+        let other = Telegram { header: Header::parse("KFM5KAIFA-METER"), elements: vec![], raw: Bytes::new(), errors: vec![] };
+        export(&other, &TariffNames::default());
+        let updated = Telegram { elements: vec![Attribute::EquipmentIdentifier("E0123456789012345".into()), Attribute::VersionInformation("50218".into()), Attribute::Version("50".into())], ..telegram };
+        export(&updated, &TariffNames::default());
+        assert!(METER_INFO.remove_label_values(&["Iskra", "M550E-1012", "50", "E0123456789012345", "50217"]).is_err());
+        assert!(METER_INFO.remove_label_values(&["Iskra", "M550E-1012", "50", "E0123456789012345", "50218"]).is_ok());
+        assert!(METER_INFO.remove_label_values(&["Kaifa", "KAIFA-METER", "", "", ""]).is_ok());
+    }
+}
@@ -0,0 +1,186 @@
+use std::io::{BufRead, ErrorKind};
+
+use anyhow::{anyhow, Context};
+use crc16::{State, X_25};
+
+use crate::binary::read_byte;
+use crate::error::DsmrError;
+
+const FLAG: u8 = 0x7e;
+
+/// LLC header in front of the first segment of every push message.
+const LLC: [u8; 3] = [0xe6, 0xe7, 0x00];
+
+/// Longest APDU to collect from segmented frames; push messages are a few hundred bytes.
+const MAX_APDU: usize = 64 * 1024;
+
+/// A single HDLC frame (type 3) as pushed by the HAN port.
+#[derive(Debug, PartialEq)]
+pub struct Frame {
+    pub segmented: bool,
+    pub information: Vec<u8>,
+}
+
+impl Frame {
+    /// Parse the bytes between the opening and closing flag, checking HCS and FCS.
+    pub fn parse(data: &[u8]) -> Result<Self, anyhow::Error> {
+        // frame format: type 0xA, segmentation bit and an 11-bit length
+        let (format, length) = match *data {
+            [hi, lo, ..]    => (hi, usize::from(hi & 0x07) << 8 | usize::from(lo)),
+            _               => return Err(anyhow!("Frame too short: {data:02x?}")),
+        };
+        if format >> 4 != 0xa {
+            return Err(anyhow!("Unsupported frame format {format:#04x}"));
+        }
+        if length != data.len() || length < 4 {
+            return Err(anyhow!("Frame length {length} does not match {} bytes read", data.len()));
+        }
+
+        let (body, fcs) = data.split_at(data.len() - 2);
+        Self::check(body, fcs, "FCS")?;
+
+        // destination and source address both end at a byte with the lowest bit set, then control
+        let mut pos = 2;
+        for _ in 0..2 {
+            pos += body.get(pos..).unwrap_or_default()
+                .iter()
+                .position(|b| b & 1 == 1)
+                .ok_or_else(|| anyhow!("Cannot find end of address in frame {data:02x?}"))? + 1;
+        }
+        pos += 1;
+
+        let information = match body.get(pos..) {
+            None | Some([])         => vec![],
+            Some([_])               => return Err(anyhow!("Truncated HCS in frame {data:02x?}")),
+            Some(rest)              => {
+                Self::check(&body[..pos], &rest[..2], "HCS")?;
+                rest[2..].to_vec()
+            },
+        };
+
+        Ok(Frame { segmented: format & 0x08 != 0, information })
+    }
+
+    fn check(data: &[u8], checksum: &[u8], name: &'static str) -> Result<(), anyhow::Error> {
+        let expected = State::<X_25>::calculate(data);
+        let actual = u16::from_le_bytes([checksum[0], checksum[1]]);
+        if expected != actual {
+            return Err(DsmrError::ChecksumMismatch { name, expected, actual }.into());
+        }
+        Ok(())
+    }
+}
+
+fn peek_byte<S: BufRead>(reader: &mut S) -> Result<u8, anyhow::Error> {
+    match reader.fill_buf()?.first() {
+        Some(&byte) => Ok(byte),
+        None        => Err(std::io::Error::from(ErrorKind::UnexpectedEof)).context("Unexpected EOF reached"),
+    }
+}
+
+/// Skip to the next opening flag and read one frame. The closing flag is left in the stream, as
+/// it may open the next frame too.
+pub fn read_frame<S: BufRead>(reader: &mut S) -> Result<Frame, anyhow::Error> {
+    while read_byte(reader)? != FLAG {}
+
+    // frames may share a flag, or be separated by several
+    let mut first = FLAG;
+    while first == FLAG {
+        first = read_byte(reader)?;
+    }
+    let second = read_byte(reader)?;
+    let length = usize::from(first & 0x07) << 8 | usize::from(second);
+
+    let mut data = vec![first, second];
+    data.resize(length.max(2), 0);
+    reader.read_exact(&mut data[2..])?;
+    if peek_byte(reader)? != FLAG {
+        return Err(anyhow!("Missing closing flag after frame {data:02x?}"));
+    }
+
+    Frame::parse(&data)
+}
+
+/// Read frames up to and including the last segment, and return the APDU they carry.
+pub fn read_apdu<S: BufRead>(reader: &mut S) -> Result<Vec<u8>, anyhow::Error> {
+    let mut apdu = vec![];
+    loop {
+        let frame = read_frame(reader)?;
+        apdu.extend(frame.information);
+        if apdu.len() > MAX_APDU {
+            return Err(anyhow!("APDU longer than {MAX_APDU} bytes"));
+        }
+        if !frame.segmented {
+            break;
+        }
+    }
+    match apdu.strip_prefix(&LLC[..]) {
+        Some(apdu)  => Ok(apdu.to_vec()),
+        None        => Err(anyhow!("Missing LLC header in APDU {apdu:02x?}")),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Wrap an information field in one or more HDLC frames.
+    pub(crate) fn frames(information: &[u8], segment_size: usize) -> Vec<u8> {
+        let segments: Vec<&[u8]> = information.chunks(segment_size).collect();
+        let mut result = vec![];
+        for (i, segment) in segments.iter().enumerate() {
+            let length = 6 + 2 + segment.len() + 2;
+            let segmented = if i + 1 < segments.len() { 0x08 } else { 0x00 };
+            let mut frame = vec![0xa0 | segmented | (length >> 8) as u8, length as u8, 0x41, 0x08, 0x83, 0x13];
+            frame.extend(State::<X_25>::calculate(&frame).to_le_bytes());
+            frame.extend(*segment);
+            frame.extend(State::<X_25>::calculate(&frame).to_le_bytes());
+            result.push(FLAG);
+            result.extend(frame);
+            result.push(FLAG);
+        }
+        result
+    }
+
+    #[test]
+    fn test_frame_without_information() -> Result<(), anyhow::Error> {
+        let frame = Frame::parse(&[0xa0, 0x07, 0x03, 0x21, 0x93, 0x0f, 0x01])?;
+        assert_eq!(frame, Frame { segmented: false, information: vec![] });
+        Ok(())
+    }
+
+    #[test]
+    fn test_bad_checksum() {
+        assert!(Frame::parse(&[0xa0, 0x07, 0x03, 0x21, 0x93, 0x0f, 0x02]).is_err());
+        let mut data = frames(&[0xe6, 0xe7, 0x00, 0x0f], 100);
+        data[9] ^= 1;
+        assert!(read_frame(&mut &data[..]).is_err());
+    }
+
+    #[test]
+    fn test_malformed() {
+        for data in [&[][..], &[0xa0], &[0xa0, 0x02], &[0xb0, 0x07, 0x03, 0x21, 0x93, 0x0f, 0x01], &[0xa0, 0x05, 0x02, 0x02, 0x02]] {
+            assert!(Frame::parse(data).is_err(), "{data:02x?}");
+        }
+    }
+
+    #[test]
+    fn test_read_apdu() -> Result<(), anyhow::Error> {
+        let information: Vec<u8> = LLC.iter().copied().chain(0..200).collect();
+        let mut data = vec![0x00, 0x7e];
+        data.extend(frames(&information, 64));
+        data.extend(frames(&[0xe6, 0xe7, 0x00, 0x0f], 64));
+        // frames sharing a flag
+        data.extend(&frames(&[0xe6, 0xe7, 0x00, 0x0e], 64)[1..]);
+        let mut reader = &data[..];
+        assert_eq!(read_apdu(&mut reader)?, (0..200).collect::<Vec<u8>>());
+        assert_eq!(read_apdu(&mut reader)?, vec![0x0f]);
+        assert_eq!(read_apdu(&mut reader)?, vec![0x0e]);
+        assert!(read_apdu(&mut reader).is_err());
+
+        // a segmented APDU that goes on for too long
+        let data = frames(&vec![0; 2 * MAX_APDU], 1024);
+        assert!(read_apdu(&mut &data[..]).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,265 @@
+use std::io::{ErrorKind, Read};
+use std::str::FromStr;
+
+use anyhow::{anyhow, Context};
+use log::debug;
+use serialport::{ClearBuffer, SerialPort};
+
+use crate::binary;
+use crate::error::{bad_frame, DsmrError};
+use crate::telegram::{Options, Telegram};
+
+const STX: u8 = 0x02;
+const ETX: u8 = 0x03;
+const ACK: u8 = 0x06;
+
+/// Request message that starts every mode C readout.
+const REQUEST: &[u8] = b"/?!\r\n";
+
+/// IEC 62056-21 protocol mode.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Mode {
+    /// Request a readout at 300 baud, then switch to the baud rate the meter proposes
+    #[default]
+    C,
+    /// Listen to the readouts the meter transmits at 2400 baud on its own
+    D,
+}
+
+impl FromStr for Mode {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text.to_ascii_lowercase().as_str() {
+            "c"     => Ok(Mode::C),
+            "d"     => Ok(Mode::D),
+            _       => Err(anyhow!("Unknown IEC 62056-21 mode {text:?}, expected c or d")),
+        }
+    }
+}
+
+impl Mode {
+    /// Baud rate to open the serial port with.
+    pub fn baud_rate(self) -> u32 {
+        match self {
+            Mode::C => 300,
+            Mode::D => 2400,
+        }
+    }
+}
+
+/// Baud rate for the character following the manufacturer in a mode C identification message.
+fn baud_rate(id: u8) -> Option<u32> {
+    match id {
+        b'0'..=b'6' => Some(300 << (id - b'0')),
+        _           => None,
+    }
+}
+
+/// Read a byte like [`binary::read_byte`], but report a meter that does not answer as such.
+fn read_byte<S: Read + ?Sized>(reader: &mut S) -> Result<u8, anyhow::Error> {
+    match binary::read_byte(reader) {
+        Ok(byte)                                        => Ok(byte),
+        Err(e) if e.kind() == ErrorKind::TimedOut       => Err(DsmrError::Timeout.into()),
+        Err(e)                                          => Err(anyhow::Error::from(e).context("Error reading from meter")),
+    }
+}
+
+fn read_line<S: Read + ?Sized>(reader: &mut S) -> Result<Vec<u8>, anyhow::Error> {
+    let mut line = vec![];
+    while line.last() != Some(&b'\n') {
+        line.push(read_byte(reader)?);
+    }
+    Ok(line)
+}
+
+/// Read the data block of a mode C readout, from STX up to ETX, and check its BCC.
+fn read_block<S: Read + ?Sized>(reader: &mut S) -> Result<Vec<u8>, anyhow::Error> {
+    while read_byte(reader)? != STX {}
+    let mut block = vec![];
+    loop {
+        match read_byte(reader)? {
+            ETX     => break,
+            byte    => block.push(byte),
+        }
+    }
+
+    // the BCC is the XOR of all bytes after STX, up to and including ETX
+    let expected = block.iter().fold(ETX, |acc, b| acc ^ b);
+    let actual = read_byte(reader)?;
+    if expected != actual {
+        return Err(DsmrError::ChecksumMismatch { name: "BCC", expected: expected.into(), actual: actual.into() }.into());
+    }
+    Ok(block)
+}
+
+/// Turn a data line into a DSMR line. Short codes like `1.8.0` leave out medium and channel, and
+/// lines with non-numeric codes like `C.1.0` or `F.F` are dropped.
+fn normalize(line: &str) -> Option<String> {
+    let (obis, _) = line.split_once('(')?;
+    let line = if obis.contains(':') { line.to_owned() } else { format!("1-0:{line}") };
+    let numeric = line.split_once('(')?.0
+        .split(['-', ':', '.'])
+        .all(|part| part.parse::<u8>().is_ok());
+    if !numeric {
+        debug!("Skipping IEC 62056-21 data line {line:?}");
+        return None;
+    }
+    Some(line)
+}
+
+/// Read one readout from a meter. In mode C this sends the request, acknowledges the
+/// identification message and switches to the proposed baud rate, so every call starts at 300 baud.
+pub fn read<P: SerialPort + ?Sized>(port: &mut P, mode: Mode, options: &Options) -> Result<Telegram, DsmrError> {
+    read_readout(port, mode, options)
+        .map_err(|e| bad_frame("IEC 62056-21", e))
+}
+
+fn read_readout<P: SerialPort + ?Sized>(port: &mut P, mode: Mode, options: &Options) -> Result<Telegram, anyhow::Error> {
+    if mode == Mode::C {
+        port.set_baud_rate(mode.baud_rate())?;
+        port.clear(ClearBuffer::All)?;
+        port.write_all(REQUEST)?;
+        port.flush()?;
+    }
+
+    while read_byte(port)? != b'/' {}
+    let identification = String::from_utf8_lossy(&read_line(port)?).trim_end().to_owned();
+    debug!("Meter identifies as {identification:?}");
+
+    let data = match mode {
+        Mode::C => {
+            // meters that cannot switch baud rates send an unknown character; stay at 300 baud
+            let id = identification.as_bytes().get(3).copied();
+            let (id, bps) = match id.and_then(|id| baud_rate(id).map(|bps| (id, bps))) {
+                Some((id, bps)) => (id, bps),
+                None            => (b'0', mode.baud_rate()),
+            };
+            port.write_all(&[ACK, b'0', id, b'0', b'\r', b'\n'])?;
+            port.flush()?;
+            port.set_baud_rate(bps)?;
+            read_block(port)?
+        },
+        Mode::D => {
+            let mut data = vec![];
+            while !data.ends_with(b"!\r\n") {
+                data.extend(read_line(port)?);
+            }
+            data
+        },
+    };
+
+    let data = String::from_utf8(data)
+        .context("Data block is not valid ASCII")?;
+    let lines: Vec<String> = [format!("/{identification}"), "".into()].into_iter()
+        .chain(data.lines().filter_map(normalize))
+        .collect();
+    Ok(Telegram::new(&lines, options))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::thread;
+    use std::time::Duration;
+
+    use serialport::TTYPort;
+
+    use super::*;
+    use crate::attribute::Attribute;
+
+    const DATA: &[u8] = b"F.F(00000000)\r\nC.1.0(12345678)\r\n1.8.0(0012345.6*kWh)\r\n2.8.0(0000012.3*kWh)\r\n1-0:32.7.0(230.1*V)\r\n!\r\n";
+
+    fn block(data: &[u8]) -> Vec<u8> {
+        let bcc = data.iter().fold(ETX, |acc, b| acc ^ b);
+        [&[STX][..], data, &[ETX, bcc]].concat()
+    }
+
+    fn pair() -> Result<(TTYPort, TTYPort), anyhow::Error> {
+        let (mut meter, mut port) = TTYPort::pair()?;
+        meter.set_timeout(Duration::from_secs(5))?;
+        port.set_timeout(Duration::from_secs(5))?;
+        Ok((meter, port))
+    }
+
+    fn expected() -> Vec<Attribute> {
+        vec![
+            Attribute::ElectricityDelivered(0, 12345.6),
+            Attribute::ElectricityReceived(0, 12.3),
+            Attribute::InstantVoltage(1, 230.1),
+        ]
+    }
+
+    #[test]
+    fn test_mode_c() -> Result<(), anyhow::Error> {
+        let (mut meter, mut port) = pair()?;
+        // the fake meter hands back its end of the pty, so it is not closed while still in use
+        let fake = thread::spawn(move || -> Result<(TTYPort, Vec<Vec<u8>>), anyhow::Error> {
+            let request = read_line(&mut meter)?;
+            meter.write_all(b"/ISK5MT174-0001\r\n")?;
+            let ack = read_line(&mut meter)?;
+            meter.write_all(&block(DATA))?;
+            Ok((meter, vec![request, ack]))
+        });
+
+        let telegram = read(&mut port, Mode::C, &Options::default())?;
+        assert_eq!(fake.join().unwrap()?.1, vec![REQUEST.to_vec(), b"\x06050\r\n".to_vec()]);
+        assert_eq!(telegram.header.model, "MT174-0001");
+        assert_eq!(telegram.elements, expected());
+        Ok(())
+    }
+
+    #[test]
+    fn test_mode_c_bad_bcc() -> Result<(), anyhow::Error> {
+        let (mut meter, mut port) = pair()?;
+        let fake = thread::spawn(move || -> Result<TTYPort, anyhow::Error> {
+            for bad in [true, false] {
+                read_line(&mut meter)?;
+                meter.write_all(b"/LGZ\xff ZMD120\r\n")?;
+                read_line(&mut meter)?;
+                let mut data = block(DATA);
+                let last = data.len() - 1;
+                data[last] ^= u8::from(bad);
+                meter.write_all(&data)?;
+            }
+            Ok(meter)
+        });
+
+        let e = read(&mut port, Mode::C, &Options::default()).unwrap_err();
+        assert!(matches!(e, DsmrError::ChecksumMismatch { name: "BCC", .. }));
+        assert!(e.is_recoverable());
+        assert_eq!(read(&mut port, Mode::C, &Options::default())?.elements, expected());
+        fake.join().unwrap()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_timeout() -> Result<(), anyhow::Error> {
+        let (_meter, mut port) = pair()?;
+        port.set_timeout(Duration::from_millis(100))?;
+        let e = read(&mut port, Mode::D, &Options::default()).unwrap_err();
+        assert!(matches!(e, DsmrError::Timeout));
+        assert!(e.is_recoverable());
+        Ok(())
+    }
+
+    #[test]
+    fn test_mode_d() -> Result<(), anyhow::Error> {
+        let (mut meter, mut port) = pair()?;
+        meter.write_all(b"noise/EMH4EHZ-H\r\n\r\n")?;
+        meter.write_all(DATA)?;
+
+        let telegram = read(&mut port, Mode::D, &Options::default())?;
+        assert_eq!(telegram.header.manufacturer_name(), Some("EMH"));
+        assert_eq!(telegram.elements, expected());
+        Ok(())
+    }
+
+    #[test]
+    fn test_baud_rate() {
+        assert_eq!(baud_rate(b'0'), Some(300));
+        assert_eq!(baud_rate(b'5'), Some(9600));
+        assert_eq!(baud_rate(b'6'), Some(19200));
+        assert_eq!(baud_rate(b'A'), None);
+    }
+}
@@ -0,0 +1,20 @@
+//! Readers for smart meter telegrams in DSMR P1, DLMS/COSEM over HDLC, SML and IEC 62056-21, and
+//! the Prometheus exporter that the `dsmr-prometheus` daemon builds on.
+//!
+//! [`reader::TelegramReader`] reads telegrams from any byte stream, and with the `async` feature
+//! `stream::TelegramStream` does the same on a tokio runtime.
+
+pub mod attribute;
+mod binary;
+pub mod error;
+pub mod timestamp;
+pub mod telegram;
+pub mod exporter;
+pub mod smarty;
+pub mod hdlc;
+pub mod dlms;
+pub mod sml;
+pub mod iec;
+pub mod reader;
+#[cfg(feature = "async")]
+pub mod stream;
@@ -1,17 +1,33 @@
-pub mod attribute;
-pub mod telegram;
-pub mod exporter;
 pub mod cli;
 
+#[cfg(not(feature = "async"))]
 use std::net::TcpStream;
+#[cfg(not(feature = "async"))]
 use std::fs::File;
-use std::io::{BufReader, Read};
-use std::time::Duration;
+#[cfg(not(feature = "async"))]
+use std::io::Read;
+use std::thread;
+use std::time::{Duration, Instant};
 
 use anyhow::Context;
-use log::{debug, info, error};
+use log::{debug, info, warn, error};
 
-use telegram::Telegram;
+use serialport::{DataBits, Parity, SerialPort};
+#[cfg(feature = "async")]
+use tokio::io::AsyncRead;
+#[cfg(feature = "async")]
+use tokio_serial::SerialPortBuilderExt;
+
+use dsmr_prometheus::{exporter, iec};
+use dsmr_prometheus::telegram::{Options, Protocol, Statistics, Telegram};
+use dsmr_prometheus::error::DsmrError;
+use dsmr_prometheus::smarty::Key;
+#[cfg(not(feature = "async"))]
+use dsmr_prometheus::reader::TelegramReader;
+#[cfg(feature = "async")]
+use dsmr_prometheus::stream::TelegramStream;
+use dsmr_prometheus::iec::Mode;
+use dsmr_prometheus::exporter::TariffNames;
 use cli::{CLI, Source};
 
 fn is_interactive() -> bool {
@@ -20,67 +36,247 @@ fn is_interactive() -> bool {
     }
 }
 
-fn main_loop<S: Read>(source: S) -> Result<(), anyhow::Error> {
-    let mut reader = BufReader::new(source);
+/// Log a recoverable error. Failures of the connection to the meter are counted, and once
+/// `max_failures` (unless 0) of them happened in a row this gives up; a bad value only means the
+/// meter sent something unexpected, so it does not count.
+fn skip(e: DsmrError, failures: &mut u32, max_failures: u32, what: &str) -> Result<(), anyhow::Error> {
+    let link_failure = e.is_link_failure();
+    let e = anyhow::Error::from(e);
+    if !link_failure {
+        *failures = 0;
+        warn!("Skipping {what}: {e:#}");
+        return Ok(());
+    }
+    *failures += 1;
+    if max_failures != 0 && *failures >= max_failures {
+        return Err(e.context(DsmrError::TooManyFailures { count: *failures }));
+    }
+    warn!("Skipping {what}: {e:#}");
+    Ok(())
+}
+
+/// Export a telegram, or skip it if it has a recoverable error.
+fn handle(result: Result<Telegram, DsmrError>, failures: &mut u32, max_failures: u32, tariff_names: &TariffNames) -> Result<(), anyhow::Error> {
+    let telegram = match result {
+        Ok(telegram)                    => { *failures = 0; telegram },
+        Err(e) if e.is_recoverable()    => return skip(e, failures, max_failures, "telegram"),
+        Err(e)                          => return Err(anyhow::Error::from(e).context("Error reading frame")),
+    };
+
+    log_bad_lines(&telegram);
+    exporter::export(&telegram, tariff_names);
+
+    debug!("{telegram:?}");
+    Ok(())
+}
+
+/// Log the lines of a telegram that were left out because they could not be parsed.
+fn log_bad_lines(telegram: &Telegram) {
+    for e in &telegram.errors {
+        let source = std::error::Error::source(e).map(|source| format!(": {source}")).unwrap_or_default();
+        warn!("Skipping line: {e}{source}");
+    }
+}
+
+#[cfg(not(feature = "async"))]
+fn main_loop<S: Read>(source: S, options: &Options, key: Option<&Key>, tariff_names: &TariffNames, max_failures: u32) -> Result<(), anyhow::Error> {
+    let mut reader = TelegramReader::new(source, options, key);
+    let mut exported = Statistics::default();
+    let mut failures = 0;
+
+    while let Some(result) = reader.next() {
+        exporter::export_statistics(&reader.statistics().since(&exported));
+        exported = reader.statistics().clone();
+        handle(result, &mut failures, max_failures, tariff_names)?;
+    }
+
+    Err(DsmrError::Eof).context("Error reading frame")
+}
+
+#[cfg(feature = "async")]
+async fn async_main_loop<S: AsyncRead + Unpin>(source: S, options: &Options, key: Option<&Key>, tariff_names: &TariffNames, max_failures: u32) -> Result<(), anyhow::Error> {
+    let mut stream = TelegramStream::new(source, options, key);
+    let mut exported = Statistics::default();
+    let mut failures = 0;
+
+    while let Some(result) = stream.next().await {
+        exporter::export_statistics(&stream.statistics().since(&exported));
+        exported = stream.statistics().clone();
+        handle(result, &mut failures, max_failures, tariff_names)?;
+    }
+
+    Err(DsmrError::Eof).context("Error reading frame")
+}
 
+fn iec_loop(port: &mut dyn SerialPort, mode: Mode, interval: Duration, options: &Options, tariff_names: &TariffNames, max_failures: u32) -> Result<(), anyhow::Error> {
+    let mut failures = 0;
     loop {
-        let telegram = Telegram::from(&mut reader)
-            .context("Error reading frame")?;
+        let start = Instant::now();
+        let result = iec::read(port, mode, options);
+        let mut statistics = Statistics::default();
+        match result {
+            Ok(ref telegram)    => statistics.bad_values += telegram.errors.len() as u64,
+            Err(ref e)          => statistics.count_error(e),
+        }
+        exporter::export_statistics(&statistics);
+        match result {
+            Ok(telegram)                    => {
+                failures = 0;
+                log_bad_lines(&telegram);
+                exporter::export(&telegram, tariff_names);
+                debug!("{telegram:?}");
+            },
+            Err(e) if e.is_recoverable()    => skip(e, &mut failures, max_failures, "readout")?,
+            Err(e)                          => return Err(anyhow::Error::from(e).context("Error reading IEC 62056-21 readout")),
+        }
 
-        exporter::export(&telegram.elements);
+        // mode D meters decide for themselves when to send
+        if mode == Mode::C {
+            thread::sleep(interval.saturating_sub(start.elapsed()));
+        }
+    }
+}
 
-        debug!("{telegram:?}");
+fn serial_format(protocol: Protocol) -> (DataBits, Parity) {
+    match protocol {
+        Protocol::Dsmr3 => (DataBits::Seven, Parity::Even),
+        Protocol::Dsmr4 => (DataBits::Eight, Parity::None),
+        Protocol::Hdlc  => (DataBits::Eight, Parity::Even),
+        Protocol::Sml   => (DataBits::Eight, Parity::None),
     }
 }
 
-fn try_main() -> Result<(), anyhow::Error> {
-    // parse program arguments
-    let cli = CLI::new()
-        .map_err(|e| {
-            println!("{e}"); // logger is not yet initialized at this point
-            e
-        })?;
+fn iec_main(tty: &str, mode: Mode, interval: Duration, options: &Options, tariff_names: &TariffNames, max_failures: u32) -> Result<(), anyhow::Error> {
+    // mode D meters stay silent between readouts, so only give up after a whole interval
+    let timeout = match mode {
+        Mode::C => Duration::from_secs(5),
+        Mode::D => interval.max(Duration::from_secs(5)),
+    };
+    let mut port = serialport::new(tty, mode.baud_rate())
+        .data_bits(DataBits::Seven)
+        .parity(Parity::Even)
+        .timeout(timeout)
+        .open()
+        .with_context(|| format!("Error opening serial port {tty}"))?;
+    iec_loop(&mut *port, mode, interval, options, tariff_names, max_failures)
+}
 
-    // initialize logger
-    env_logger::Builder::from_default_env()
-        .filter_level(cli.verbosity.log_level_filter())
-        .format_timestamp(is_interactive().then(|| env_logger::fmt::TimestampPrecision::Millis))
-        .target(env_logger::Target::Stdout)
-        .init();
+/// Read from one source on a tokio runtime. IEC 62056-21 readouts switch baud rates halfway, so
+/// they still block, on a thread of their own.
+#[cfg(feature = "async")]
+async fn async_source(source: Source, options: Options, key: Option<Key>, tariff_names: TariffNames, max_failures: u32) -> Result<(), anyhow::Error> {
+    match source {
+        Source::Socket(ref host) => {
+            let source = tokio::net::TcpStream::connect(host).await
+                .with_context(|| format!("Error connecting to {host}"))?;
+            async_main_loop(source, &options, key.as_ref(), &tariff_names, max_failures).await?;
+        },
+        Source::Serial(ref tty, bps) => {
+            let (data_bits, parity) = serial_format(options.protocol);
+            let source = tokio_serial::new(tty, bps)
+                .data_bits(data_bits)
+                .parity(parity)
+                .open_native_async()
+                .with_context(|| format!("Error opening serial port {tty}"))?;
+            async_main_loop(source, &options, key.as_ref(), &tariff_names, max_failures).await?;
+        },
+        Source::File(ref path) => {
+            let source = tokio::fs::File::open(path).await
+                .with_context(|| format!("Error opening {path:?}"))?;
+            async_main_loop(source, &options, key.as_ref(), &tariff_names, max_failures).await?;
+        },
+        Source::Iec(tty, mode, interval) => {
+            tokio::task::spawn_blocking(move || iec_main(&tty, mode, interval, &options, &tariff_names, max_failures)).await??;
+        },
+    }
 
-    // say something
-    info!("{} {} starting", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
-    info!("Reading from {}", cli.source());
-    info!("Prometheus listening on http://{}/", cli.listen);
-    debug!("{cli:?}");
+    Ok(())
+}
 
-    // start prometheus_exporter
-    exporter::start(&cli.listen)?;
+/// Serve the metrics and read from all sources on a tokio runtime, until one of them fails.
+#[cfg(feature = "async")]
+async fn async_main(cli: &CLI, options: &Options) -> Result<(), anyhow::Error> {
+    let mut tasks = tokio::task::JoinSet::new();
+    tasks.spawn(exporter::serve(cli.listen.clone()));
+    for source in cli.sources() {
+        tasks.spawn(async_source(source, options.clone(), cli.key.clone(), cli.tariff_names.clone(), cli.max_failures));
+    }
+    while let Some(result) = tasks.join_next().await {
+        result??;
+    }
+
+    Ok(())
+}
 
-    // connect to source and start source-specific main loop
-    match cli.source() {
+#[cfg(not(feature = "async"))]
+fn run(cli: &CLI, options: &Options) -> Result<(), anyhow::Error> {
+    // without the async feature, the command line takes exactly one source
+    exporter::start(&cli.listen)?;
+    match cli.sources().remove(0) {
         Source::Socket(ref host) => {
             let source = TcpStream::connect(host)
                 .with_context(|| format!("Error connecting to {host}"))?;
-            main_loop(source)?;
+            main_loop(source, options, cli.key.as_ref(), &cli.tariff_names, cli.max_failures)?;
         },
         Source::Serial(ref tty, bps) => {
+            let (data_bits, parity) = serial_format(options.protocol);
             let source = serialport::new(tty, bps)
+                .data_bits(data_bits)
+                .parity(parity)
                 .timeout(Duration::from_secs(5))
                 .open()
                 .with_context(|| format!("Error opening serial port {tty}"))?;
-            main_loop(source)?;
+            main_loop(source, options, cli.key.as_ref(), &cli.tariff_names, cli.max_failures)?;
         },
         Source::File(ref path) => {
             let source = File::options().read(true).open(path)
                 .with_context(|| format!("Error opening {path:?}"))?;
-            main_loop(source)?;
+            main_loop(source, options, cli.key.as_ref(), &cli.tariff_names, cli.max_failures)?;
+        },
+        Source::Iec(ref tty, mode, interval) => {
+            iec_main(tty, mode, interval, options, &cli.tariff_names, cli.max_failures)?;
         },
     }
 
     Ok(())
 }
 
+#[cfg(feature = "async")]
+fn run(cli: &CLI, options: &Options) -> Result<(), anyhow::Error> {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?
+        .block_on(async_main(cli, options))
+}
+
+fn try_main() -> Result<(), anyhow::Error> {
+    // parse program arguments
+    let cli = CLI::new()
+        .map_err(|e| {
+            println!("{e}"); // logger is not yet initialized at this point
+            e
+        })?;
+
+    // initialize logger
+    env_logger::Builder::from_default_env()
+        .filter_level(cli.verbosity.log_level_filter())
+        .format_timestamp(is_interactive().then_some(env_logger::fmt::TimestampPrecision::Millis))
+        .target(env_logger::Target::Stdout)
+        .init();
+
+    // say something
+    info!("{} {} starting", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+    for source in cli.sources() {
+        info!("Reading from {source}");
+    }
+    info!("Prometheus listening on http://{}/", cli.listen);
+    debug!("{cli:?}");
+
+    // start the exporter, connect to the sources and start their source-specific main loops
+    let options = cli.options();
+    run(&cli, &options)
+}
+
 fn main() {
     if let Err(e) = try_main() {
         error!("Exiting with error: {e:#}");
@@ -0,0 +1,168 @@
+use std::io::{BufRead, BufReader, Read};
+
+use crate::dlms;
+use crate::error::{bad_frame, DsmrError};
+use crate::hdlc;
+use crate::sml;
+use crate::smarty::{Decryptor, Key};
+use crate::telegram::{Options, Protocol, Statistics, Telegram};
+
+/// Reads telegrams in any of the supported protocols from a byte stream.
+///
+/// Errors in a single telegram or frame, like a bad checksum in any of the protocols, are returned
+/// and reading goes on with the next one. Iteration ends when the source runs out of data, or after
+/// an I/O error.
+pub struct TelegramReader<R: Read> {
+    reader: BufReader<R>,
+    options: Options,
+    decryptor: Option<Decryptor>,
+    statistics: Statistics,
+    done: bool,
+}
+
+impl<R: Read> TelegramReader<R> {
+    /// Read from `source`, decrypting with `key` if given.
+    pub fn new(source: R, options: &Options, key: Option<&Key>) -> Self {
+        TelegramReader {
+            reader: BufReader::new(source),
+            options: options.clone(),
+            decryptor: key.map(Decryptor::new),
+            statistics: Statistics::default(),
+            done: false,
+        }
+    }
+
+    /// Problems found since the reader was created.
+    pub fn statistics(&self) -> &Statistics {
+        &self.statistics
+    }
+
+    fn read(&mut self) -> Result<Telegram, DsmrError> {
+        read_telegram(&mut self.reader, &self.options, self.decryptor.as_mut(), &mut self.statistics)
+    }
+}
+
+/// Read the next telegram from `reader` in the protocol from `options`, decrypting it if there is a
+/// decryptor.
+pub(crate) fn read_telegram<R: BufRead>(reader: &mut R, options: &Options, decryptor: Option<&mut Decryptor>, statistics: &mut Statistics) -> Result<Telegram, DsmrError> {
+    let result = match (options.protocol, decryptor) {
+        (Protocol::Hdlc, decryptor)     => hdlc::read_apdu(reader)
+            .and_then(|apdu| dlms::telegram(&apdu, options, decryptor))
+            .map_err(|e| bad_frame("HDLC", e)),
+        (Protocol::Sml, _)              => sml::read_file(reader)
+            .and_then(|messages| sml::telegram(&messages, options))
+            .map_err(|e| bad_frame("SML", e)),
+        (_, Some(decryptor))            => decryptor.read(reader)
+            .map_err(|e| bad_frame("encrypted", e))
+            .and_then(|plaintext| Telegram::read(&mut &plaintext[..], options, statistics)),
+        (_, None)                       => Telegram::read(reader, options, statistics),
+    };
+    match result {
+        Ok(ref telegram)    => statistics.bad_values += telegram.errors.len() as u64,
+        Err(ref e)          => statistics.count_error(e),
+    }
+    result
+}
+
+impl<R: Read> Iterator for TelegramReader<R> {
+    type Item = Result<Telegram, DsmrError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.read() {
+            Ok(telegram)                    => Some(Ok(telegram)),
+            Err(e) if e.is_eof()            => { self.done = true; None },
+            Err(e)                          => { self.done = !e.is_recoverable(); Some(Err(e)) },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::smarty;
+
+    #[test]
+    fn test_reader() -> Result<(), anyhow::Error> {
+        let telegram = include_bytes!("../telegram.txt");
+        let mut corrupted = telegram.to_vec();
+        corrupted[40] ^= 1;
+        let data = [&b"noise"[..], telegram, &corrupted, telegram, b"/ISK5\\2M550E"].concat();
+
+        let mut reader = TelegramReader::new(&data[..], &Options::default(), None);
+        assert_eq!(reader.next().unwrap()?.raw, telegram[..]);
+        let e = reader.next().unwrap().unwrap_err();
+        assert!(matches!(e, DsmrError::CrcMismatch { .. }));
+        assert!(reader.next().unwrap().is_ok());
+        assert!(reader.next().is_none());
+        assert!(reader.next().is_none());
+        assert_eq!(reader.statistics(), &Statistics { crc_failures: 1, resyncs: 1, bytes_discarded: 17, bad_frames: 0, bad_values: 0 });
+        Ok(())
+    }
+
+    /// Read `data`, which holds a bad frame between two good ones.
+    fn bad_frame_between_good_ones(data: &[u8], options: &Options, key: Option<&Key>) -> Statistics {
+        let mut reader = TelegramReader::new(data, options, key);
+        assert!(reader.next().unwrap().is_ok());
+        let e = reader.next().unwrap().unwrap_err();
+        assert!(e.is_recoverable(), "{e}");
+        assert!(reader.next().unwrap().is_ok());
+        assert!(reader.next().is_none());
+        reader.statistics().clone()
+    }
+
+    #[test]
+    fn test_bad_frames() -> Result<(), anyhow::Error> {
+        // HDLC frame with an intact FCS, but an APDU that is not a data-notification
+        let mut apdu = vec![0xe6, 0xe7, 0x00, 0x0f, 0x00, 0x00, 0x00, 0x00, 0x00];
+        apdu.extend([0x02, 0x02, 0x09, 0x06, 0x01, 0x00, 0x01, 0x07, 0x00, 0xff, 0x06, 0x00, 0x00, 0x05, 0x8c]);
+        let mut bad = apdu.clone();
+        bad[3] = 0x0e;
+        let data = [hdlc::tests::frames(&apdu, 100), hdlc::tests::frames(&bad, 100), hdlc::tests::frames(&apdu, 100)].concat();
+        let options = Options { protocol: Protocol::Hdlc, ..Options::default() };
+        assert_eq!(bad_frame_between_good_ones(&data, &options, None).bad_frames, 1);
+
+        let good = sml::tests::file(&sml::tests::get_list_response());
+        let mut bad = good.clone();
+        let last = bad.len() - 1;
+        bad[last] ^= 1;
+        let data = [&good[..], &bad, &good].concat();
+        let options = Options { protocol: Protocol::Sml, ..Options::default() };
+        assert_eq!(bad_frame_between_good_ones(&data, &options, None).crc_failures, 1);
+
+        let key: Key = smarty::tests::KEY.parse()?;
+        let telegram = include_bytes!("../telegram.txt");
+        let mut bad = smarty::tests::encrypt(&key, 2, telegram);
+        let last = bad.len() - 1;
+        bad[last] ^= 1;
+        let data = [smarty::tests::encrypt(&key, 1, telegram), bad, smarty::tests::encrypt(&key, 3, telegram)].concat();
+        assert_eq!(bad_frame_between_good_ones(&data, &Options::default(), Some(&key)).bad_frames, 1);
+
+        let data = [&b"\xdb\x00\x05hello/ISK5"[..], &smarty::tests::encrypt(&key, 1, telegram)].concat();
+        let mut reader = TelegramReader::new(&data[..], &Options::default(), Some(&key));
+        assert!(reader.next().unwrap().is_ok());
+        assert!(reader.next().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_binary_eof() {
+        let options = Options { protocol: Protocol::Hdlc, ..Options::default() };
+        assert!(TelegramReader::new(&b"\x7e\xa0"[..], &options, None).next().is_none());
+
+        // a frame with a corrupted FCS is skipped, and the good frame after it is still read
+        let apdu = [0xe6, 0xe7, 0x00, 0x0f, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x02, 0x09, 0x06, 0x01, 0x00, 0x01, 0x07, 0x00, 0xff, 0x06, 0x00, 0x00, 0x05, 0x8c];
+        let mut data = hdlc::tests::frames(&apdu, 100);
+        let fcs = data.len() - 2;
+        data[fcs] ^= 1;
+        data.extend(hdlc::tests::frames(&apdu, 100));
+        let mut reader = TelegramReader::new(&data[..], &options, None);
+        let e = reader.next().unwrap().unwrap_err();
+        assert!(matches!(e, DsmrError::ChecksumMismatch { name: "FCS", .. }));
+        assert!(reader.next().unwrap().is_ok());
+        assert!(reader.next().is_none());
+        assert_eq!(reader.statistics().crc_failures, 1);
+    }
+}
@@ -0,0 +1,324 @@
+use std::io::Read;
+use std::str::FromStr;
+
+use aes_gcm::aead::consts::U12;
+use aes_gcm::aes::Aes128;
+use aes_gcm::{AeadInPlace, AesGcm, KeyInit, Nonce, Tag};
+use anyhow::anyhow;
+use ctr::cipher::{KeyIvInit, StreamCipher};
+use log::debug;
+
+use crate::binary::{read_byte, read_n, GENERAL_GLO_CIPHERING};
+use crate::error::{is_eof, is_io};
+
+/// Authentication key that Smarty meters use as additional authenticated data.
+const AUTHENTICATION_KEY: [u8; 16] = [
+    0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff,
+];
+
+const TAG_LEN: usize = 12;
+
+/// Security control bytes of authenticated and encrypted frames, and of encrypted-only frames.
+const AUTHENTICATED_ENCRYPTION: u8 = 0x30;
+const ENCRYPTION_ONLY: u8 = 0x20;
+
+const SYSTEM_TITLE_LEN: usize = 8;
+
+type Cipher = AesGcm<Aes128, U12, U12>;
+type Ctr = ctr::Ctr32BE<Aes128>;
+
+/// AES-128 key of a Luxembourg Smarty meter, as provided by the grid operator.
+#[derive(Clone, PartialEq)]
+pub struct Key(pub [u8; 16]);
+
+impl std::fmt::Debug for Key {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Key(..)")
+    }
+}
+
+impl FromStr for Key {
+    type Err = anyhow::Error;
+
+    /// Parse a key written as 32 hex digits.
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        if text.len() != 32 || !text.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(anyhow!("Expected 32 hex digits in key"));
+        }
+        let mut key = [0; 16];
+        for (i, byte) in key.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&text[2*i..2*i+2], 16)?;
+        }
+        Ok(Key(key))
+    }
+}
+
+/// Decrypts general-glo-ciphering frames into plaintext DSMR telegrams.
+pub struct Decryptor {
+    key: Key,
+    cipher: Cipher,
+    frame_counter: Option<u32>,
+    /// Bytes after the start of a frame that failed, to scan again for the next frame
+    pending: Vec<u8>,
+}
+
+impl Decryptor {
+    pub fn new(key: &Key) -> Self {
+        Decryptor {
+            key: key.clone(),
+            cipher: Cipher::new(&key.0.into()),
+            frame_counter: None,
+            pending: vec![],
+        }
+    }
+
+    /// Decrypt a single frame, rejecting bad tags and frame counters that do not increase. Frames
+    /// with security control 0x30 are authenticated and encrypted, those with 0x20 (as sent by some
+    /// Austrian meters) only encrypted, without a tag.
+    pub fn decrypt(&mut self, frame: &[u8]) -> Result<Vec<u8>, anyhow::Error> {
+        let (system_title, payload) = Self::split_frame(frame)?;
+
+        let (&security_control, payload) = payload.split_first()
+            .ok_or_else(|| anyhow!("Missing security control byte"))?;
+        let tag_len = match security_control {
+            AUTHENTICATED_ENCRYPTION    => TAG_LEN,
+            ENCRYPTION_ONLY             => 0,
+            _                           => return Err(anyhow!("Unsupported security control {security_control:#04x}")),
+        };
+        if payload.len() < 4 + tag_len {
+            return Err(anyhow!("Frame too short: {} bytes of payload", payload.len()));
+        }
+        let (frame_counter, payload) = payload.split_at(4);
+        let (ciphertext, tag) = payload.split_at(payload.len() - tag_len);
+        let frame_counter = u32::from_be_bytes(frame_counter.try_into()?);
+
+        if let Some(last) = self.frame_counter.filter(|&last| frame_counter <= last) {
+            return Err(anyhow!("Frame counter {frame_counter} does not follow {last}; replayed frame?"));
+        }
+
+        let nonce: Vec<u8> = system_title.iter().chain(&frame_counter.to_be_bytes()).copied().collect();
+        let mut plaintext = ciphertext.to_vec();
+        if security_control == ENCRYPTION_ONLY {
+            // GCM without the tag is AES-CTR, with the counter starting at 2 after the nonce
+            let mut iv = [0; 16];
+            iv[..12].copy_from_slice(&nonce);
+            iv[15] = 2;
+            Ctr::new(&self.key.0.into(), &iv.into()).apply_keystream(&mut plaintext);
+        } else {
+            let aad: Vec<u8> = std::iter::once(security_control).chain(AUTHENTICATION_KEY).collect();
+            self.cipher.decrypt_in_place_detached(Nonce::from_slice(&nonce), &aad, &mut plaintext, Tag::from_slice(tag))
+                .map_err(|_| anyhow!("Authentication tag mismatch in frame {frame_counter}"))?;
+        }
+
+        self.frame_counter = Some(frame_counter);
+        Ok(plaintext)
+    }
+
+    /// Skip to the next frame in the stream, then read and decrypt it. A `0xdb` in line noise looks
+    /// like the start of a frame, so after a bad header or a failed decryption the stream is scanned
+    /// again from the byte after it. Errors of the source itself are returned right away.
+    pub fn read<S: Read>(&mut self, reader: &mut S) -> Result<Vec<u8>, anyhow::Error> {
+        let original = self.pending.clone();
+        loop {
+            let pending = std::mem::take(&mut self.pending);
+            let mut input = (&pending[..]).chain(&mut *reader);
+            let mut frame = vec![];
+            let result = Self::read_frame(&mut input, &mut frame);
+            let rest = input.into_inner().0;
+            let rescan = || frame.get(1..).unwrap_or_default().iter().chain(rest).copied().collect();
+
+            match result {
+                Ok(())                      => {
+                    let result = self.decrypt(&frame);
+                    self.pending = if result.is_ok() { rest.to_vec() } else { rescan() };
+                    return result;
+                },
+                // start over on the same bytes once more data has arrived
+                Err(e) if is_eof(&e)        => { self.pending = original; return Err(e) },
+                Err(e) if is_io(&e)         => {
+                    self.pending = frame.iter().chain(rest).copied().collect();
+                    return Err(e);
+                },
+                Err(e)                      => { debug!("Skipping noise: {e:#}"); self.pending = rescan() },
+            }
+        }
+    }
+
+    /// Skip to the next general-glo-ciphering tag and read the frame it starts into `frame`.
+    fn read_frame<S: Read>(reader: &mut S, frame: &mut Vec<u8>) -> Result<(), anyhow::Error> {
+        while read_byte(reader)? != GENERAL_GLO_CIPHERING {}
+        frame.push(GENERAL_GLO_CIPHERING);
+
+        // system title
+        let title_len = read_byte(reader)?;
+        frame.push(title_len);
+        if usize::from(title_len) != SYSTEM_TITLE_LEN {
+            return Err(anyhow!("System title of {title_len} bytes, expected {SYSTEM_TITLE_LEN}"));
+        }
+        frame.extend(read_n(reader, SYSTEM_TITLE_LEN)?);
+
+        // BER length: either the length itself, or 0x81/0x82 and one or two length bytes
+        let encoding = read_byte(reader)?;
+        frame.push(encoding);
+        let length = match encoding {
+            0x81            => read_n(reader, 1)?,
+            0x82            => read_n(reader, 2)?,
+            n if n < 0x80   => vec![n],
+            n               => return Err(anyhow!("Unsupported length encoding {n:#04x}")),
+        };
+        if encoding >= 0x80 {
+            frame.extend(&length);
+        }
+        let length = length.iter().fold(0, |acc, &b| acc << 8 | usize::from(b));
+        frame.extend(read_n(reader, length)?);
+        Ok(())
+    }
+
+    fn split_frame(frame: &[u8]) -> Result<(&[u8], &[u8]), anyhow::Error> {
+        match *frame {
+            [GENERAL_GLO_CIPHERING, title_len, ref rest @ ..] if rest.len() > title_len.into() => {
+                let (system_title, rest) = rest.split_at(title_len.into());
+                let (length, payload) = match *rest {
+                    [0x81, n, ref payload @ ..]             => (usize::from(n), payload),
+                    [0x82, hi, lo, ref payload @ ..]        => (usize::from(u16::from_be_bytes([hi, lo])), payload),
+                    [n, ref payload @ ..] if n < 0x80       => (usize::from(n), payload),
+                    _                                       => return Err(anyhow!("Unsupported length encoding")),
+                };
+                if payload.len() != length {
+                    return Err(anyhow!("Frame length {} does not match header length {length}", payload.len()));
+                }
+                Ok((system_title, payload))
+            },
+            _ => Err(anyhow!("Not a general-glo-ciphering frame")),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) const KEY: &str = "000102030405060708090A0B0C0D0E0F";
+    const SYSTEM_TITLE: [u8; 8] = *b"SAG\x01\x02\x03\x04\x05";
+
+    pub(crate) fn encrypt(key: &Key, frame_counter: u32, plaintext: &[u8]) -> Vec<u8> {
+        let cipher = Cipher::new(&key.0.into());
+        let nonce: Vec<u8> = SYSTEM_TITLE.iter().chain(&frame_counter.to_be_bytes()).copied().collect();
+        let aad: Vec<u8> = std::iter::once(0x30).chain(AUTHENTICATION_KEY).collect();
+        let mut ciphertext = plaintext.to_vec();
+        let tag = cipher.encrypt_in_place_detached(Nonce::from_slice(&nonce), &aad, &mut ciphertext).unwrap();
+
+        let length = (1 + 4 + ciphertext.len() + TAG_LEN) as u16;
+        let mut frame = vec![GENERAL_GLO_CIPHERING, 8];
+        frame.extend(SYSTEM_TITLE);
+        frame.push(0x82);
+        frame.extend(length.to_be_bytes());
+        frame.push(0x30);
+        frame.extend(frame_counter.to_be_bytes());
+        frame.extend(ciphertext);
+        frame.extend(tag);
+        frame
+    }
+
+    #[test]
+    fn test_key() {
+        assert_eq!(KEY.parse::<Key>().unwrap().0[15], 0x0f);
+        assert!("000102".parse::<Key>().is_err());
+        assert!("000102030405060708090A0B0C0D0EXX".parse::<Key>().is_err());
+    }
+
+    #[test]
+    fn test_decrypt() -> Result<(), anyhow::Error> {
+        let key: Key = KEY.parse()?;
+        let plaintext = include_bytes!("../telegram.txt");
+        let mut decryptor = Decryptor::new(&key);
+        assert_eq!(decryptor.decrypt(&encrypt(&key, 1, plaintext))?, plaintext);
+        assert_eq!(decryptor.decrypt(&encrypt(&key, 2, plaintext))?, plaintext);
+        Ok(())
+    }
+
+    #[test]
+    fn test_encryption_only() -> Result<(), anyhow::Error> {
+        // the ciphertext of GCM, without the tag and with another security control byte
+        let key: Key = KEY.parse()?;
+        let plaintext = b"\x0f\x00\x00\x00\x01\x00\x02\x00";
+        let mut frame = encrypt(&key, 1, plaintext);
+        frame.truncate(frame.len() - TAG_LEN);
+        frame[12] -= TAG_LEN as u8;
+        frame[13] = ENCRYPTION_ONLY;
+        assert_eq!(Decryptor::new(&key).decrypt(&frame)?, plaintext);
+
+        frame[13] = 0x10;
+        assert!(Decryptor::new(&key).decrypt(&frame).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_read() -> Result<(), anyhow::Error> {
+        let key: Key = KEY.parse()?;
+        // noise with a general-glo-ciphering tag, and one that runs into the first frame
+        let mut stream = b"noise\xdb\x00\x05hello\xdb\x08".to_vec();
+        stream.extend(encrypt(&key, 1, b"first"));
+        stream.extend(encrypt(&key, 2, b"second"));
+        let mut reader = &stream[..];
+        let mut decryptor = Decryptor::new(&key);
+        assert!(decryptor.read(&mut reader).is_err());
+        assert_eq!(decryptor.read(&mut reader)?, b"first");
+        assert_eq!(decryptor.read(&mut reader)?, b"second");
+        assert!(decryptor.read(&mut reader).is_err());
+        Ok(())
+    }
+
+    /// A source that fails like an unplugged serial adapter.
+    struct Unplugged;
+
+    impl Read for Unplugged {
+        fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+            Err(std::io::Error::other("device disconnected"))
+        }
+    }
+
+    #[test]
+    fn test_read_error() -> Result<(), anyhow::Error> {
+        let key: Key = KEY.parse()?;
+        let mut decryptor = Decryptor::new(&key);
+        for mut reader in [(&[][..]).chain(Unplugged), (&b"noise\xdb\x00\xdb\x08SAG"[..]).chain(Unplugged)] {
+            let e = decryptor.read(&mut reader).unwrap_err();
+            assert!(e.chain().any(|cause| cause.is::<std::io::Error>()), "{e:#}");
+            assert!(!is_eof(&e));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_bad_tag() -> Result<(), anyhow::Error> {
+        let key: Key = KEY.parse()?;
+        let mut frame = encrypt(&key, 1, b"/ISK5\\2M550E-1012\r\n");
+        let last = frame.len() - 1;
+        frame[last] ^= 1;
+        assert!(Decryptor::new(&key).decrypt(&frame).is_err());
+
+        let other: Key = "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF".parse()?;
+        assert!(Decryptor::new(&other).decrypt(&encrypt(&key, 1, b"test")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_replay() -> Result<(), anyhow::Error> {
+        let key: Key = KEY.parse()?;
+        let mut decryptor = Decryptor::new(&key);
+        decryptor.decrypt(&encrypt(&key, 5, b"test"))?;
+        assert!(decryptor.decrypt(&encrypt(&key, 5, b"test")).is_err());
+        assert!(decryptor.decrypt(&encrypt(&key, 4, b"test")).is_err());
+        decryptor.decrypt(&encrypt(&key, 6, b"test"))?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_malformed() {
+        let mut decryptor = Decryptor::new(&KEY.parse().unwrap());
+        for frame in [&b""[..], b"\xdb", b"\xdb\x08SAG", b"\xdb\x08SAG\x01\x02\x03\x04\x05\x82\x00\x20\x30", b"\x00\x08"] {
+            assert!(decryptor.decrypt(frame).is_err());
+        }
+    }
+}
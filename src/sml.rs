@@ -0,0 +1,287 @@
+use std::io::Read;
+
+use anyhow::{anyhow, Context};
+use crc16::{State, X_25};
+
+use crate::binary::{self, read_array, read_byte, take, MAX_DEPTH};
+use crate::dlms::{Data, Register};
+use crate::error::DsmrError;
+use crate::telegram::{Options, Telegram};
+
+const ESCAPE: [u8; 4] = [0x1b; 4];
+const START: [u8; 4] = [0x01; 4];
+const END: u8 = 0x1a;
+
+const GET_LIST_RESPONSE: u64 = 0x0701;
+
+/// SML values, as encoded in type-length-value form.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    None,
+    OctetString(Vec<u8>),
+    Boolean(bool),
+    Integer(i64),
+    Unsigned(u64),
+    List(Vec<Value>),
+}
+
+impl Value {
+    /// Decode one value from the front of `input`.
+    pub fn parse(input: &mut &[u8]) -> Result<Self, anyhow::Error> {
+        Self::parse_nested(input, 0)
+    }
+
+    fn parse_nested(input: &mut &[u8], depth: usize) -> Result<Self, anyhow::Error> {
+        let tl = take(input, 1)?[0];
+        if tl == 0x00 || tl == 0x01 {
+            // end of message, or optional value not present
+            return Ok(Self::None);
+        }
+
+        // type-length: bit 7 means another length nibble follows, bits 6-4 are the type
+        let kind = (tl >> 4) & 0x07;
+        if kind == 0b111 && depth >= MAX_DEPTH {
+            return Err(anyhow!("Lists nested more than {MAX_DEPTH} deep"));
+        }
+        let mut length = usize::from(tl & 0x0f);
+        let mut tl_length = 1;
+        let mut more = tl & 0x80 != 0;
+        while more {
+            let next = take(input, 1)?[0];
+            more = next & 0x80 != 0;
+            length = length << 4 | usize::from(next & 0x0f);
+            tl_length += 1;
+        }
+
+        // for everything but lists the length includes the type-length bytes
+        let data_length = || length.checked_sub(tl_length)
+            .ok_or_else(|| anyhow!("Length {length} shorter than type-length field"));
+        match kind {
+            0b000   => Ok(Self::OctetString(take(input, data_length()?)?.to_vec())),
+            0b100   => Ok(Self::Boolean(take(input, data_length()?)?.iter().any(|&b| b != 0))),
+            0b101   => Ok(Self::Integer(Self::parse_int(take(input, data_length()?)?, true)?)),
+            0b110   => Ok(Self::Unsigned(Self::parse_int(take(input, data_length()?)?, false)? as u64)),
+            0b111   => Ok(Self::List((0..length).map(|_| Self::parse_nested(input, depth + 1)).collect::<Result<_, _>>()?)),
+            _       => Err(anyhow!("Unsupported type-length field {tl:#04x}")),
+        }
+    }
+
+    fn parse_int(bytes: &[u8], signed: bool) -> Result<i64, anyhow::Error> {
+        if bytes.is_empty() || bytes.len() > 8 {
+            return Err(anyhow!("Unsupported integer length {}", bytes.len()));
+        }
+        let fill = if signed && bytes[0] & 0x80 != 0 { 0xff } else { 0x00 };
+        let mut buf = [fill; 8];
+        buf[8 - bytes.len()..].copy_from_slice(bytes);
+        Ok(i64::from_be_bytes(buf))
+    }
+
+    fn list(&self) -> Option<&[Value]> {
+        match self {
+            Self::List(items)   => Some(items),
+            _                   => None,
+        }
+    }
+
+    fn to_data(&self) -> Option<Data> {
+        match self {
+            Self::OctetString(bytes)    => Some(Data::OctetString(bytes.clone())),
+            Self::Integer(n)            => Some(Data::Integer((*n).into())),
+            Self::Unsigned(n)           => Some(Data::Integer((*n).into())),
+            _                           => None,
+        }
+    }
+}
+
+/// Skip to the next start sequence, read up to the end sequence and check the CRC, which meters
+/// send low byte first. Returns the messages in between, with escape sequences removed.
+pub fn read_file<S: Read>(reader: &mut S) -> Result<Vec<u8>, anyhow::Error> {
+    let start: Vec<u8> = ESCAPE.iter().chain(&START).copied().collect();
+    let mut window: Vec<u8> = vec![];
+    while window != start {
+        if window.len() == start.len() {
+            window.remove(0);
+        }
+        window.push(read_byte(reader)?);
+    }
+
+    let mut raw = start;
+    let mut messages = vec![];
+    loop {
+        let block = read_array::<4, S>(reader)?;
+        raw.extend(block);
+        if block != ESCAPE {
+            messages.extend(block);
+            continue;
+        }
+
+        let block = read_array::<4, S>(reader)?;
+        match block {
+            ESCAPE                          => { raw.extend(block); messages.extend(block) },
+            [END, fill, _, _]               => {
+                raw.extend(&block[..2]);
+                let expected = State::<X_25>::calculate(&raw);
+                let actual = u16::from_le_bytes([block[2], block[3]]);
+                if expected != actual {
+                    return Err(DsmrError::ChecksumMismatch { name: "CRC", expected, actual }.into());
+                }
+                messages.truncate(messages.len().saturating_sub(fill.into()));
+                return Ok(messages);
+            },
+            _                               => return Err(anyhow!("Unexpected escape sequence {block:02x?}")),
+        }
+    }
+}
+
+/// Turn the values of an SML GetListResponse into registers.
+fn registers(entries: &[Value]) -> Vec<Register> {
+    let mut result = vec![];
+    for entry in entries.iter().filter_map(Value::list) {
+        let [Value::OctetString(obis), _, _, unit, scaler, value, ..] = entry else { continue };
+        let (Ok(obis), Some(value)) = (obis.as_slice().try_into(), value.to_data()) else { continue };
+        let unit = match unit {
+            Value::Unsigned(unit)   => u8::try_from(*unit).ok(),
+            _                       => None,
+        };
+        let scaler = match scaler {
+            Value::Integer(scaler)  => i8::try_from(*scaler).unwrap_or_default(),
+            _                       => 0,
+        };
+
+        // SML sends signed sums of active power; DSMR splits these into delivered and received
+        let [a, b, c, d, e, f] = obis;
+        match (a, c, d, &value) {
+            (1, 16 | 36 | 56 | 76, 7, Data::Integer(n)) => {
+                let (delivered, received) = if c == 16 { (1, 2) } else { (c - 15, c - 14) };
+                result.push(Register { obis: [a, b, delivered, d, e, f], value: Data::Integer((*n).max(0)), scaler, unit });
+                result.push(Register { obis: [a, b, received, d, e, f], value: Data::Integer((-n).max(0)), scaler, unit });
+            },
+            _ => result.push(Register { obis, value, scaler, unit }),
+        }
+    }
+    result
+}
+
+/// Decode the messages of an SML file into a telegram.
+pub fn telegram(messages: &[u8], options: &Options) -> Result<Telegram, anyhow::Error> {
+    let mut input = messages;
+    let mut header = String::new();
+    let mut lines = vec![];
+
+    while input.iter().any(|&b| b != 0) {
+        let message = Value::parse(&mut input)
+            .context("Error parsing SML message")?;
+        let Some([_, _, _, Value::List(body), ..]) = message.list() else {
+            return Err(anyhow!("Unexpected SML message {message:?}"));
+        };
+        let [Value::Unsigned(GET_LIST_RESPONSE), Value::List(response)] = body.as_slice() else { continue };
+        if let [_, Value::OctetString(server_id), _, _, Value::List(entries), ..] = response.as_slice() {
+            header = server_id.iter().map(|b| format!("{b:02X}")).collect();
+            lines.extend(registers(entries).iter().filter_map(Register::to_line));
+        }
+    }
+
+    if lines.is_empty() {
+        return Err(anyhow!("No GetListResponse in SML file"));
+    }
+    Ok(binary::telegram(&header, lines, options))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::attribute::Attribute;
+
+    fn octets(bytes: &[u8]) -> Vec<u8> {
+        [&[0x01 + bytes.len() as u8][..], bytes].concat()
+    }
+
+    fn entry(obis: [u8; 6], unit: u8, scaler: i8, value: &[u8]) -> Vec<u8> {
+        [&[0x77][..], &octets(&obis), &[0x01, 0x01, 0x62, unit, 0x52, scaler as u8], value, &[0x01]].concat()
+    }
+
+    pub(crate) fn get_list_response() -> Vec<u8> {
+        let entries = [
+            entry([1, 0, 1, 8, 0, 255], 30, -1, &[0x65, 0x00, 0x0f, 0x42, 0x40]),
+            entry([1, 0, 2, 8, 0, 255], 30, -1, &[0x65, 0x00, 0x00, 0x03, 0xe8]),
+            entry([1, 0, 16, 7, 0, 255], 27, 0, &[0x55, 0xff, 0xff, 0xff, 0x38]),
+            entry([1, 0, 32, 7, 0, 255], 35, -1, &[0x63, 0x09, 0x03]),
+        ].concat();
+        [
+            &[0x76, 0x01, 0x01, 0x01, 0x72, 0x63, 0x07, 0x01, 0x77, 0x01][..],
+            &octets(b"\x0a\x01EMH\x00\x00\x01\x02\x03"),
+            &[0x01, 0x01, 0x74][..],
+            &entries,
+            &[0x01, 0x01, 0x63, 0x00, 0x00, 0x00],
+        ].concat()
+    }
+
+    pub(crate) fn file(messages: &[u8]) -> Vec<u8> {
+        let fill = (4 - messages.len() % 4) % 4;
+        let mut data: Vec<u8> = ESCAPE.iter().chain(&START).chain(messages).copied().collect();
+        data.extend(vec![0; fill]);
+        data.extend(ESCAPE);
+        data.extend([END, fill as u8]);
+        data.extend(State::<X_25>::calculate(&data).to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn test_value() -> Result<(), anyhow::Error> {
+        let tests: [(&[u8], Value); 6] = [
+            (&[0x01],                                   Value::None),
+            (&[0x62, 0x1e],                             Value::Unsigned(30)),
+            (&[0x52, 0xff],                             Value::Integer(-1)),
+            (&[0x03, 0x41, 0x42],                       Value::OctetString(b"AB".to_vec())),
+            (&[0x72, 0x62, 0x01, 0x01],                 Value::List(vec![Value::Unsigned(1), Value::None])),
+            (&[0x80, 0x04, 0x41, 0x42],                 Value::OctetString(b"AB".to_vec())),
+        ];
+        for (bytes, expected) in tests {
+            assert_eq!(Value::parse(&mut &bytes[..])?, expected);
+        }
+        for bytes in [&[][..], &[0x63, 0x01], &[0x72, 0x62], &[0x91], &[0x5a, 0, 0, 0, 0, 0, 0, 0, 0, 0], &[0x30]] {
+            assert!(Value::parse(&mut &bytes[..]).is_err(), "{bytes:02x?}");
+        }
+        assert!(Value::parse(&mut &[0x71; 50_000][..]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_file() -> Result<(), anyhow::Error> {
+        let mut data = b"noise".to_vec();
+        data.extend(file(&[0x01, 0x02, 0x03, 0x04, 0x1b, 0x1b, 0x1b, 0x1b, 0x1b, 0x1b, 0x1b, 0x1b, 0x05]));
+        data.extend(file(&[0x04]));
+        let mut reader = &data[..];
+        assert_eq!(read_file(&mut reader)?, vec![0x01, 0x02, 0x03, 0x04, 0x1b, 0x1b, 0x1b, 0x1b, 0x05]);
+        assert_eq!(read_file(&mut reader)?, vec![0x04]);
+        assert!(read_file(&mut reader).is_err());
+
+        let mut data = file(&[0x01]);
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        assert!(read_file(&mut &data[..]).is_err());
+
+        // the CRC goes low byte first, like the HDLC FCS; big-endian is a mismatch
+        let mut data = file(&get_list_response());
+        let crc = data.len() - 2;
+        data.swap(crc, crc + 1);
+        assert!(read_file(&mut &data[..]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_telegram() -> Result<(), anyhow::Error> {
+        let data = file(&get_list_response());
+        let messages = read_file(&mut &data[..])?;
+        let telegram = telegram(&messages, &Options::default())?;
+        assert_eq!(telegram.header.model, "0A01454D480000010203");
+        assert_eq!(telegram.elements, vec![
+            Attribute::ElectricityDelivered(0, 100.0),
+            Attribute::ElectricityReceived(0, 0.1),
+            Attribute::ActualPowerDelivered(0.0),
+            Attribute::ActualPowerReceived(0.2),
+            Attribute::InstantVoltage(1, 230.7),
+        ]);
+        Ok(())
+    }
+}
@@ -0,0 +1,114 @@
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::error::DsmrError;
+use crate::reader::read_telegram;
+use crate::smarty::{Decryptor, Key};
+use crate::telegram::{Options, Statistics, Telegram};
+
+/// Bytes to keep while looking for a complete telegram; older bytes are discarded.
+const MAX_BUFFER: usize = 64 * 1024;
+
+/// Reads telegrams in any of the supported protocols from an async byte stream, like
+/// [`TelegramReader`](crate::reader::TelegramReader) does from a blocking one.
+///
+/// Received bytes are buffered until they hold a complete telegram, so the blocking decoders can
+/// be used without ever waiting for the source.
+pub struct TelegramStream<R: AsyncRead + Unpin> {
+    source: R,
+    buffer: Vec<u8>,
+    options: Options,
+    decryptor: Option<Decryptor>,
+    statistics: Statistics,
+    done: bool,
+}
+
+impl<R: AsyncRead + Unpin> TelegramStream<R> {
+    /// Read from `source`, decrypting with `key` if given.
+    pub fn new(source: R, options: &Options, key: Option<&Key>) -> Self {
+        TelegramStream {
+            source,
+            buffer: vec![],
+            options: options.clone(),
+            decryptor: key.map(Decryptor::new),
+            statistics: Statistics::default(),
+            done: false,
+        }
+    }
+
+    /// Problems found since the stream was created.
+    pub fn statistics(&self) -> &Statistics {
+        &self.statistics
+    }
+
+    /// Wait for the next telegram. Returns `None` once the source is closed, or after an error
+    /// that leaves the stream unusable.
+    pub async fn next(&mut self) -> Option<Result<Telegram, DsmrError>> {
+        if self.done {
+            return None;
+        }
+        let mut closed = false;
+        loop {
+            // statistics of attempts on an incomplete telegram would be counted again on the next one
+            let mut statistics = Statistics::default();
+            let mut input = &self.buffer[..];
+            let result = read_telegram(&mut input, &self.options, self.decryptor.as_mut(), &mut statistics);
+            let consumed = self.buffer.len() - input.len();
+
+            let result = match result {
+                Err(e) if e.is_eof() && !closed => None,
+                Err(e) if e.is_eof()            => { self.done = true; Some(None) },
+                Err(e)                          => { self.done = !e.is_recoverable(); Some(Some(Err(e))) },
+                Ok(telegram)                    => Some(Some(Ok(telegram))),
+            };
+            if let Some(result) = result {
+                self.statistics.merge(&statistics);
+                self.buffer.drain(..consumed);
+                return result;
+            }
+
+            if self.buffer.len() > MAX_BUFFER {
+                let excess = self.buffer.len() - MAX_BUFFER;
+                self.statistics.bytes_discarded += excess as u64;
+                self.buffer.drain(..excess);
+            }
+            match self.source.read_buf(&mut self.buffer).await {
+                Ok(0)   => closed = true,
+                Ok(_)   => (),
+                Err(e)  => { self.done = true; return Some(Err(e.into())) },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncWriteExt;
+    use tokio::runtime::Builder;
+
+    use super::*;
+
+    #[test]
+    fn test_stream() -> Result<(), anyhow::Error> {
+        let telegram = include_bytes!("../telegram.txt");
+        let mut corrupted = telegram.to_vec();
+        corrupted[40] ^= 1;
+        let data = [&b"noise"[..], telegram, &corrupted, telegram, b"/ISK5\\2M550E"].concat();
+
+        Builder::new_current_thread().build()?.block_on(async {
+            // a small pipe hands out the data in chunks of at most 7 bytes
+            let (mut writer, reader) = tokio::io::duplex(7);
+            let writer = tokio::spawn(async move { writer.write_all(&data).await });
+
+            let mut stream = TelegramStream::new(reader, &Options::default(), None);
+            assert!(stream.next().await.unwrap().is_ok());
+            let e = stream.next().await.unwrap().unwrap_err();
+            assert!(matches!(e, DsmrError::CrcMismatch { .. }));
+            assert!(stream.next().await.unwrap().is_ok());
+            assert!(stream.next().await.is_none());
+            assert!(stream.next().await.is_none());
+            assert_eq!(stream.statistics(), &Statistics { crc_failures: 1, resyncs: 1, bytes_discarded: 17, bad_frames: 0, bad_values: 0 });
+            writer.await??;
+            Ok(())
+        })
+    }
+}
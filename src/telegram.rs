@@ -1,63 +1,578 @@
-use std::io::{BufReader, Read, BufRead};
+use std::collections::{HashMap, HashSet};
+use std::io::BufRead;
+use std::str::FromStr;
+use std::sync::Mutex;
 
 use anyhow::anyhow;
+use bytes::Bytes;
+use lazy_static::lazy_static;
 
 use log::{debug, warn};
 use crc16::{State, ARC};
 
 use crate::attribute::Attribute;
+use crate::error::DsmrError;
+use crate::timestamp::Timezone;
+
+/// Longest telegram to collect; real ones are a few kilobytes, so longer ones are dropped as noise.
+const MAX_FRAME: usize = 64 * 1024;
+
+lazy_static! {
+    static ref UNKNOWN_OBIS: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+}
+
+/// Telegram format spoken by the meter.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Protocol {
+    /// DSMR 2.2 and 3.0: 9600 baud 7E1, telegram ends in a bare `!`
+    Dsmr3,
+    /// DSMR 4 and 5: 115200 baud 8N1, telegram ends in `!` and a CRC16
+    #[default]
+    Dsmr4,
+    /// DLMS/COSEM push messages in HDLC frames, as sent by Norwegian and Austrian HAN ports
+    Hdlc,
+    /// SML 1.04 files, as sent by German eHZ and mME meters over an optical head
+    Sml,
+}
+
+impl FromStr for Protocol {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text {
+            "dsmr3"     => Ok(Self::Dsmr3),
+            "dsmr4"     => Ok(Self::Dsmr4),
+            "hdlc"      => Ok(Self::Hdlc),
+            "sml"       => Ok(Self::Sml),
+             _          => Err(anyhow!("Unknown protocol {text:?}")),
+        }
+    }
+}
+
+impl Protocol {
+    pub fn baud_rate(&self) -> u32 {
+        match self {
+            Self::Dsmr3 => 9600,
+            Self::Dsmr4 => 115200,
+            Self::Hdlc  => 2400,
+            Self::Sml   => 9600,
+        }
+    }
+}
+
+/// OBIS conventions followed by the meter, where countries differ.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Profile {
+    /// DSMR for telegrams that carry a DSMR version or hex-encoded equipment identifier, Nordic otherwise
+    #[default]
+    Auto,
+    /// Dutch, Belgian and Luxembourg DSMR meters
+    Dsmr,
+    /// Swedish and other Nordic ASCII HAN ports: plain-text meter ID in `0-0:96.1.0`, and
+    /// electricity registers on channel 0 or 1
+    Nordic,
+}
+
+impl FromStr for Profile {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text {
+            "auto"      => Ok(Self::Auto),
+            "dsmr"      => Ok(Self::Dsmr),
+            "nordic"    => Ok(Self::Nordic),
+             _          => Err(anyhow!("Unknown profile {text:?}")),
+        }
+    }
+}
+
+impl Profile {
+    /// Pick a profile for the data lines of a telegram.
+    fn detect(self, lines: &[(usize, String)]) -> Self {
+        match self {
+            Self::Auto if lines.iter().any(|(_, line)| line.starts_with("1-3:0.2.8") || line.starts_with("0-0:96.1.1"))
+                        => Self::Dsmr,
+            Self::Auto  => Self::Nordic,
+            profile     => profile,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    pub protocol: Protocol,
+    pub timezone: Timezone,
+    pub profile: Profile,
+}
+
+/// Identification line at the top of a telegram, like `/ISK5\2M550E-1012`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Header {
+    /// FLAG manufacturer ID, like `ISK`; empty if the header does not start with one
+    pub manufacturer: String,
+    /// IEC 62056-21 baud rate character
+    pub baud_rate: Option<char>,
+    /// Model identifier, without the `\W` enhanced identification
+    pub model: String,
+}
+
+impl Header {
+    /// Split a header into manufacturer, baud rate character and model. Headers that do not
+    /// follow IEC 62056-21, like those of DLMS and SML meters, end up in `model` as a whole.
+    pub fn parse(text: &str) -> Self {
+        let mut chars = text.chars();
+        let manufacturer: String = chars.by_ref().take(3).collect();
+        match chars.next() {
+            Some(baud_rate @ ('0'..='9' | 'A'..='I')) if manufacturer.len() == 3 && manufacturer.chars().all(|c| c.is_ascii_alphabetic()) => {
+                let model = chars.as_str();
+                let model = match model.strip_prefix('\\') {
+                    Some(enhanced)  => enhanced.get(1..).unwrap_or_default(),
+                    None            => model,
+                };
+                Header { manufacturer, baud_rate: Some(baud_rate), model: model.into() }
+            },
+            _ => Header { manufacturer: String::new(), baud_rate: None, model: text.into() },
+        }
+    }
+
+    /// Name of the manufacturer, for well-known FLAG IDs.
+    pub fn manufacturer_name(&self) -> Option<&'static str> {
+        // a lowercase third letter only signals a shorter reaction time
+        match self.manufacturer.to_ascii_uppercase().as_str() {
+            "ADN"   => Some("Aidon"),
+            "EBZ"   => Some("eBZ"),
+            "EMH"   => Some("EMH"),
+            "ESY"   => Some("EasyMeter"),
+            "ISK"   => Some("Iskra"),
+            "KAM"   => Some("Kamstrup"),
+            "KFM"   => Some("Kaifa"),
+            "LGZ"   => Some("Landis+Gyr"),
+            "SAG"   => Some("Sagemcom"),
+            "XMX"   => Some("Xemex"),
+            _       => None,
+        }
+    }
+}
+
+/// Counts of problems found while reading telegrams from a byte stream.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Statistics {
+    /// Telegrams whose CRC did not match
+    pub crc_failures: u64,
+    /// Times the framer restarted on a `/` after skipping bytes
+    pub resyncs: u64,
+    /// Bytes skipped outside of complete telegrams
+    pub bytes_discarded: u64,
+    /// Binary frames that could not be decoded or authenticated
+    pub bad_frames: u64,
+    /// Lines with an invalid OBIS code or a value that could not be parsed
+    pub bad_values: u64,
+}
+
+impl Statistics {
+    /// Counts added since an earlier copy of these statistics.
+    pub fn since(&self, earlier: &Statistics) -> Statistics {
+        Statistics {
+            crc_failures: self.crc_failures - earlier.crc_failures,
+            resyncs: self.resyncs - earlier.resyncs,
+            bytes_discarded: self.bytes_discarded - earlier.bytes_discarded,
+            bad_frames: self.bad_frames - earlier.bad_frames,
+            bad_values: self.bad_values - earlier.bad_values,
+        }
+    }
+
+    /// Add the counts of `other` to these statistics.
+    pub fn merge(&mut self, other: &Statistics) {
+        self.crc_failures += other.crc_failures;
+        self.resyncs += other.resyncs;
+        self.bytes_discarded += other.bytes_discarded;
+        self.bad_frames += other.bad_frames;
+        self.bad_values += other.bad_values;
+    }
+
+    /// Count an error from one of the decoders; DSMR CRC mismatches are counted by
+    /// [`Telegram::read`] itself.
+    pub fn count_error(&mut self, e: &DsmrError) {
+        match e {
+            DsmrError::ChecksumMismatch { .. }          => self.crc_failures += 1,
+            DsmrError::BadFrame { .. }                  => self.bad_frames += 1,
+            DsmrError::InvalidObis { .. } | DsmrError::BadValue { .. }
+                                                        => self.bad_values += 1,
+            _                                           => (),
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct Telegram {
-    pub header: String,
-    pub elements: Vec<Attribute>
+    pub header: Header,
+    pub elements: Vec<Attribute>,
+    /// The verified frame of a DSMR telegram, from `/` up to and including the line ending after
+    /// the CRC. Empty for telegrams decoded from other protocols.
+    pub raw: Bytes,
+    /// Lines that could not be parsed, and are left out of `elements`.
+    pub errors: Vec<DsmrError>,
 }
 
 impl Telegram {
-    fn new<T: AsRef<str>>(data: &[T]) -> Result<Self, anyhow::Error> {
+    /// Parse the lines of a telegram, keeping those that parse so one bad value does not lose all
+    /// other readings.
+    pub(crate) fn new<T: AsRef<str>>(data: &[T], options: &Options) -> Self {
+        let lines = Self::join_continuations(data);
+        let options = Options { profile: options.profile.detect(&lines), ..options.clone() };
+        let mut elements = vec![];
+        let mut errors = vec![];
+        for (number, line) in &lines {
+            match Attribute::parse(line, &options) {
+                Ok(attr)    => elements.push(attr),
+                Err(e)      => errors.push(e.at_line(*number)),
+            }
+        }
+        let header = data.first().map(AsRef::as_ref).unwrap_or_default();
         let result = Telegram {
-            header: data[0].as_ref()[1..].trim_end().into(),
-            elements: data.iter()
-                .skip(2)
-                .map(|e| e.as_ref().trim_end().parse())
-                .collect::<Result<Vec<Attribute>, anyhow::Error>>()?,
+            header: Header::parse(header.strip_prefix('/').unwrap_or(header).trim_end()),
+            elements: Self::resolve_device_types(elements),
+            raw: Bytes::new(),
+            errors,
         };
-        Ok(result)
+        result.log_unknown();
+        result
+    }
+
+    // DSMR 3 puts the gas reading on its own line after `0-n:24.3.0`; some Nordic meters leave
+    // out the empty line after the header. Lines keep their number, counting the header as 1.
+    fn join_continuations<T: AsRef<str>>(data: &[T]) -> Vec<(usize, String)> {
+        let mut lines: Vec<(usize, String)> = vec![];
+        for (number, line) in data.iter().enumerate().skip(1).map(|(i, line)| (i + 1, line.as_ref().trim_end())) {
+            match lines.last_mut() {
+                _ if line.is_empty()                        => (),
+                Some((_, last)) if line.starts_with('(')    => last.push_str(line),
+                _                                           => lines.push((number, line.into())),
+            }
+        }
+        lines
     }
 
-    pub fn from<S: Read>(reader: &mut BufReader<S>) -> Result<Telegram, anyhow::Error> {
-        let mut result = vec![];
-        let mut crc16 = State::<ARC>::new();
+    fn resolve_device_types(elements: Vec<Attribute>) -> Vec<Attribute> {
+        let device_types: HashMap<u8, u8> = elements.iter()
+            .filter_map(|e| match *e {
+                Attribute::GasEquipmentDeviceType(n, device_type) => Some((n, device_type)),
+                _                                                 => None,
+            })
+            .collect();
+        elements.into_iter()
+            .map(|e| match e.channel().and_then(|n| device_types.get(&n)) {
+                Some(&device_type)  => e.with_device_type(device_type),
+                None                => e,
+            })
+            .collect()
+    }
 
-        loop {
-            // read a line
-            let mut line = String::new();
-            if reader.read_line(&mut line)? == 0 {
-                return Err(anyhow!("Unexpected EOF reached"));
+    fn log_unknown(&self) {
+        let mut seen = UNKNOWN_OBIS.lock().unwrap_or_else(|e| e.into_inner());
+        for attr in &self.elements {
+            if let Attribute::Unknown { obis, values } = attr {
+                if seen.insert(obis.clone()) {
+                    warn!("Ignoring unknown OBIS code {obis} with values {values:?}");
+                }
             }
+        }
+    }
 
-            // line is not last line: update CRC16-ARC and store it
-            if ! line.starts_with('!') {
-                crc16.update(line.as_bytes());
-                result.push(line);
-                continue;
+    /// Read the raw bytes of the next telegram, from `/` up to and including the line with `!`.
+    /// Bytes before the `/` are skipped, and a new `/` restarts the telegram. A telegram longer
+    /// than [`MAX_FRAME`] is skipped as well, up to the next `/`.
+    fn read_frame<R: BufRead>(reader: &mut R, statistics: &mut Statistics) -> Result<(Vec<u8>, usize), DsmrError> {
+        let mut frame = vec![];
+        let mut bang = None;
+        let mut skipped = 0;
+        loop {
+            let buf = reader.fill_buf()?;
+            if buf.is_empty() {
+                statistics.bytes_discarded += skipped + frame.len() as u64;
+                return Err(DsmrError::Eof);
+            }
+            for (i, &byte) in buf.iter().enumerate() {
+                match byte {
+                    b'/'                        => {
+                        skipped += frame.len() as u64;
+                        if skipped > 0 {
+                            statistics.resyncs += 1;
+                            statistics.bytes_discarded += skipped;
+                            skipped = 0;
+                        }
+                        frame.clear();
+                        bang = None;
+                    },
+                    _ if frame.is_empty()       => { skipped += 1; continue },
+                    b'!' if bang.is_none()      => bang = Some(frame.len()),
+                    _                           => (),
+                }
+                if frame.len() == MAX_FRAME {
+                    debug!("Dropping telegram longer than {MAX_FRAME} bytes");
+                    skipped += frame.len() as u64 + 1;
+                    frame.clear();
+                    bang = None;
+                    continue;
+                }
+                frame.push(byte);
+                if let (b'\n', Some(bang)) = (byte, bang) {
+                    reader.consume(i + 1);
+                    return Ok((frame, bang));
+                }
             }
-            else {
-                crc16.update(b"!");
+            let length = buf.len();
+            reader.consume(length);
+        }
+    }
+
+    pub fn from<R: BufRead>(reader: &mut R, options: &Options) -> Result<Telegram, DsmrError> {
+        Self::read(reader, options, &mut Statistics::default())
+    }
+
+    /// Read the next telegram like [`Telegram::from`], counting CRC failures and skipped bytes.
+    pub fn read<R: BufRead>(reader: &mut R, options: &Options, statistics: &mut Statistics) -> Result<Telegram, DsmrError> {
+        let (frame, bang) = Self::read_frame(reader, statistics)?;
+        let (data, trailer) = frame.split_at(bang + 1);
+        let trailer = String::from_utf8_lossy(trailer);
+
+        // DSMR 3 telegrams end in a bare `!`, later ones in a CRC16-ARC over all bytes up to it
+        if options.protocol != Protocol::Dsmr3 {
+            let expected = State::<ARC>::calculate(data);
+            let actual = trailer.trim_end();
+            if !actual.eq_ignore_ascii_case(&format!("{expected:04X}")) {
+                debug!("{:?} {actual:?} {expected:04X}", String::from_utf8_lossy(data));
+                statistics.crc_failures += 1;
+                return Err(DsmrError::CrcMismatch { expected, actual: actual.into() });
             }
+        }
+
+        let text = String::from_utf8_lossy(&data[..bang]);
+        let lines: Vec<&str> = text.lines().collect();
+        let telegram = Telegram::new(&lines, options);
+        Ok(Telegram { raw: frame.into(), ..telegram })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::BufReader;
+
+    use crc16::{State, ARC};
+
+    use super::{Header, MAX_FRAME, Options, Profile, Protocol, Statistics, Telegram};
+    use crate::attribute::Attribute;
+    use crate::error::DsmrError;
+
+    fn frame(lines: &[&str]) -> Vec<u8> {
+        let mut data: Vec<u8> = lines.iter().flat_map(|l| format!("{l}\r\n").into_bytes()).collect();
+        data.push(b'!');
+        let crc = State::<ARC>::calculate(&data);
+        data.extend(format!("{crc:04X}\r\n").into_bytes());
+        data
+    }
+
+    #[test]
+    fn test_telegram() -> Result<(), anyhow::Error> {
+        let mut reader = BufReader::new(&include_bytes!("../telegram.txt")[..]);
+        let telegram = Telegram::from(&mut reader, &Options::default())?;
+        assert_eq!(telegram.header, Header { manufacturer: "ISK".into(), baud_rate: Some('5'), model: "M550E-1012".into() });
+        assert_eq!(telegram.header.manufacturer_name(), Some("Iskra"));
+        assert_eq!(telegram.elements.len(), 23);
+        Ok(())
+    }
+
+    #[test]
+    fn test_cold_meter() -> Result<(), anyhow::Error> {
+        let data = frame(&["/ISK5\\2M550E-1012", "", "0-2:24.1.0(010)", "0-2:24.2.1(220611160000S)(00012.500*GJ)"]);
+        let telegram = Telegram::from(&mut BufReader::new(&data[..]), &Options::default())?;
+        assert!(matches!(telegram.elements[1], Attribute::ColdDelivered(2, _, v) if v == 12.5));
+        Ok(())
+    }
 
-            // bad CRC16-ARC: complain and reset and attempt again
-            if line != format!("!{:04X}\r\n", crc16.get()) {
-                debug!("{result:?} {line:?} {:04X}", crc16.get());
-                warn!("CRC mismatch; resyncing");
-                result = vec![];
-                crc16 = State::<ARC>::new();
-                // TODO: prevent endless loop here by trying a reasonable number of times
-                continue;
+    #[test]
+    fn test_heat_meter_volume() -> Result<(), anyhow::Error> {
+        let data = frame(&["/ISK5\\2M550E-1012", "", "0-2:24.1.0(004)", "0-2:24.2.1(220611160000S)(00123.456*GJ)",
+                                                  "0-2:24.2.1(220611160000S)(00042.000*m3)"]);
+        let telegram = Telegram::from(&mut BufReader::new(&data[..]), &Options::default())?;
+        assert!(matches!(telegram.elements[1], Attribute::HeatDelivered(2, _, v) if v == 123.456));
+        assert!(matches!(telegram.elements[2], Attribute::ThermalVolume(2, _, v) if v == 42.0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_water_meter() -> Result<(), anyhow::Error> {
+        let data = frame(&["/ISK5\\2M550E-1012", "", "0-1:24.1.0(003)", "0-1:24.2.1(220611160000S)(03814.705*m3)",
+                                                  "0-2:24.1.0(007)", "0-2:24.2.1(220611160000S)(00123.456*m3)"]);
+        let telegram = Telegram::from(&mut BufReader::new(&data[..]), &Options::default())?;
+        assert!(matches!(telegram.elements[1], Attribute::GasDelivered(1, _, v) if v == 3814.705));
+        assert!(matches!(telegram.elements[3], Attribute::WaterDelivered(2, _, v) if v == 123.456));
+        Ok(())
+    }
+
+    #[test]
+    fn test_dsmr3() -> Result<(), anyhow::Error> {
+        let data = [
+            "/ISk5\\2ME382-1003", "", "0-0:96.1.1(4B413650303035303637323538303130)", "1-0:1.8.1(00185.000*kWh)",
+            "0-0:96.14.0(0001)", "1-0:1.7.0(0000.98*kW)", "0-1:24.1.0(3)", "0-1:96.1.0(3238313031353431303034303232323131)",
+            "0-1:24.3.0(120517020000)(08)(60)(1)(0-1:24.2.1)(m3)", "(00124.477)", "0-1:24.4.0(1)", "!", ""
+        ].join("\r\n");
+        let options = Options { protocol: Protocol::Dsmr3, ..Options::default() };
+        let telegram = Telegram::from(&mut BufReader::new(data.as_bytes()), &options)?;
+        assert_eq!(telegram.header.model, "ME382-1003");
+        assert_eq!(telegram.header.manufacturer_name(), Some("Iskra"));
+        assert_eq!(telegram.elements.len(), 8);
+        assert_eq!(telegram.elements[1], Attribute::ElectricityDelivered(1, 185.0));
+        assert!(matches!(telegram.elements[6], Attribute::GasDelivered(1, _, v) if v == 124.477));
+        assert!(Telegram::from(&mut BufReader::new(data.as_bytes()), &Options::default()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_unknown_obis() -> Result<(), anyhow::Error> {
+        let data = frame(&["/ISK5\\2M550E-1012", "", "1-3:0.2.8(50)", "0-0:96.99.9(42)", "1-0:1.7.0(00.123*kW)"]);
+        let telegram = Telegram::from(&mut BufReader::new(&data[..]), &Options::default())?;
+        assert_eq!(telegram.elements, vec![
+            Attribute::Version("50".into()),
+            Attribute::Unknown { obis: "0-0:96.99.9".into(), values: vec!["42".into()] },
+            Attribute::ActualPowerDelivered(0.123),
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_nordic() -> Result<(), anyhow::Error> {
+        let data = frame(&[
+            "/ELL5\\253833635_A", "0-0:1.0.0(210217184019W)", "0-0:96.1.0(7359992890941742)", "1-0:1.8.0(00006678.394*kWh)",
+            "1-1:2.8.0(00000000.000*kWh)", "1-0:4.7.0(0000.309*kvar)", "1-0:44.7.0(0000.161*kvar)", "1-0:52.7.0(240.1*V)",
+        ]);
+        let telegram = Telegram::from(&mut BufReader::new(&data[..]), &Options::default())?;
+        assert_eq!(telegram.header.manufacturer, "ELL");
+        assert_eq!(telegram.header.manufacturer_name(), None);
+        assert_eq!(telegram.elements[1..], [
+            Attribute::EquipmentIdentifier("7359992890941742".into()),
+            Attribute::ElectricityDelivered(0, 6678.394),
+            Attribute::ElectricityReceived(0, 0.0),
+            Attribute::ActualReactivePowerReceived(0.309),
+            Attribute::InstantReactivePowerReceived(2, 0.161),
+            Attribute::InstantVoltage(2, 240.1),
+        ]);
+
+        // the DSMR profile decodes `0-n:96.1.0` as a hex-encoded M-Bus equipment identifier
+        let options = Options { profile: Profile::Dsmr, ..Options::default() };
+        let telegram = Telegram::from(&mut BufReader::new(&data[..]), &options)?;
+        assert!(matches!(telegram.errors[..], [DsmrError::BadValue { line: 3, .. }]));
+        assert_eq!(telegram.elements.len(), 6);
+        Ok(())
+    }
+
+    #[test]
+    fn test_header() {
+        assert_eq!(Header::parse("KFM5KAIFA-METER"), Header { manufacturer: "KFM".into(), baud_rate: Some('5'), model: "KAIFA-METER".into() });
+        assert_eq!(Header::parse("Kamstrup_V0001"), Header { manufacturer: "".into(), baud_rate: None, model: "Kamstrup_V0001".into() });
+        assert_eq!(Header::parse("0A01454D480000010203").model, "0A01454D480000010203");
+        assert_eq!(Header::parse("ISK5\\").model, "");
+        assert_eq!(Header::parse("").model, "");
+    }
+
+    #[test]
+    fn test_malformed_telegrams() {
+        // none of these may panic; whether they parse does not matter
+        let corpus: &[&[&str]] = &[&[], &[""], &["/"], &["é"], &["/é", "é"], &["/ISK5\\", "", "("], &["", "", "1-0:1.8.1()()"]];
+        for data in corpus {
+            let _ = Telegram::new(data, &Options::default());
+        }
+
+        // flip bytes all over a real telegram, fixing up the CRC so the lines get parsed
+        let telegram = String::from_utf8_lossy(include_bytes!("../telegram.txt"));
+        let lines: Vec<&str> = telegram.lines().take_while(|line| !line.starts_with('!')).collect();
+        let mut seed: u32 = 1;
+        for _ in 0..2000 {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            let mut mutated: Vec<String> = lines.iter().map(|line| line.to_string()).collect();
+            let line = &mut mutated[(seed >> 8) as usize % lines.len()];
+            let pos = (seed >> 16) as usize % (line.len() + 1);
+            let replacement = ["", "(", ")", "*", "é", "0", "A", ")(", "9999999999"][(seed >> 4) as usize % 9];
+            if line.is_char_boundary(pos) {
+                line.truncate(pos);
+                line.push_str(replacement);
             }
+            let data = frame(&mutated.iter().map(String::as_str).collect::<Vec<_>>());
+            let _ = Telegram::from(&mut BufReader::new(&data[..]), &Options::default());
+        }
+    }
+
+    #[test]
+    fn test_errors() -> Result<(), anyhow::Error> {
+        let mut data = frame(&["/ISK5\\2M550E-1012", "", "1-0:1.8.1(000001.000*kWh)"]);
+        data[31] = b'2';
+        data.extend(frame(&["/ISK5\\2M550E-1012", "", "1-0:1.8.1(000001.000*kWh)", "1-0:1.8.2(bad*kWh)"]));
+        data.extend(frame(&["/ISK5\\2M550E-1012", "", "1-0:1.8.1(000001.000*kWh)"]));
+        let mut reader = BufReader::new(&data[..]);
+        assert!(matches!(Telegram::from(&mut reader, &Options::default()), Err(DsmrError::CrcMismatch { .. })));
+        let telegram = Telegram::from(&mut reader, &Options::default())?;
+        assert_eq!(telegram.elements, vec![Attribute::ElectricityDelivered(1, 1.0)]);
+        assert!(matches!(telegram.errors[..], [DsmrError::BadValue { line: 4, .. }]));
+        assert_eq!(Telegram::from(&mut reader, &Options::default())?.elements, vec![Attribute::ElectricityDelivered(1, 1.0)]);
+        assert!(matches!(Telegram::from(&mut reader, &Options::default()), Err(DsmrError::Eof)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_framer() -> Result<(), anyhow::Error> {
+        let good = frame(&["/ISK5\\2M550E-1012", "", "1-0:1.8.1(000001.000*kWh)"]);
+        let mut lf_only = b"/ISK5\\2M550E-1012\n\n1-0:1.8.1(000001.000*kWh)\n!".to_vec();
+        lf_only.extend(format!("{:04X}\n", State::<ARC>::calculate(&lf_only)).into_bytes());
 
-            // good CRC16-ARC: instantiate new Telegram
-            return Telegram::new(&result);
+        // start mid-telegram, non-UTF-8 noise, a telegram cut off by the next one, and LF-only endings
+        let noise: &[u8] = b"8.2(000002.000*kWh)\r\n!1234\r\n\xff\xfe\x00";
+        let cut_off: &[u8] = b"/ISK5\\2M550E-1012\r\n\r\n1-0:1.8";
+        let data = [noise, cut_off, &good, b"\xff", &lf_only].concat();
+
+        let mut reader = BufReader::with_capacity(7, &data[..]);
+        let mut statistics = Statistics::default();
+        for raw in [&good, &lf_only] {
+            let telegram = Telegram::read(&mut reader, &Options::default(), &mut statistics)?;
+            assert_eq!(telegram.elements, vec![Attribute::ElectricityDelivered(1, 1.0)]);
+            assert_eq!(telegram.raw, raw[..]);
         }
+        assert!(matches!(Telegram::read(&mut reader, &Options::default(), &mut statistics), Err(DsmrError::Eof)));
+        assert_eq!(statistics, Statistics {
+            crc_failures: 0,
+            resyncs: 3,
+            bytes_discarded: (noise.len() + cut_off.len() + 1) as u64,
+            bad_frames: 0,
+            bad_values: 0,
+        });
+        Ok(())
+    }
+
+    #[test]
+    fn test_statistics() -> Result<(), anyhow::Error> {
+        let mut data = frame(&["/ISK5\\2M550E-1012", "", "1-0:1.8.1(000001.000*kWh)"]);
+        data[20] ^= 1;
+        data.extend(b"garbage at the end");
+
+        let mut reader = &data[..];
+        let mut statistics = Statistics::default();
+        assert!(matches!(Telegram::read(&mut reader, &Options::default(), &mut statistics), Err(DsmrError::CrcMismatch { .. })));
+        assert!(matches!(Telegram::read(&mut reader, &Options::default(), &mut statistics), Err(DsmrError::Eof)));
+        assert_eq!(statistics, Statistics { crc_failures: 1, resyncs: 0, bytes_discarded: 18, bad_frames: 0, bad_values: 0 });
+
+        // a value the meter got wrong is counted, but is no failure of the connection
+        let e = DsmrError::InvalidObis { line: 3, obis: "1-0:1.8".into(), raw: "1-0:1.8(1)".into() };
+        statistics.count_error(&e);
+        assert_eq!(statistics.bad_values, 1);
+        assert!(e.is_recoverable() && !e.is_link_failure());
+
+        let mut data = b"/ISK5\\2M550E-1012\r\n\r\n".to_vec();
+        data.extend(b"1-0:1.8.1(000001.000*kWh)\r\n".repeat(MAX_FRAME / 10));
+        let length = data.len() as u64;
+        data.extend(frame(&["/ISK5\\2M550E-1012", "", "1-0:1.8.1(000001.000*kWh)"]));
+        let mut reader = &data[..];
+        let mut statistics = Statistics::default();
+        Telegram::read(&mut reader, &Options::default(), &mut statistics)?;
+        assert_eq!(statistics, Statistics { crc_failures: 0, resyncs: 1, bytes_discarded: length, bad_frames: 0, bad_values: 0 });
+        Ok(())
     }
 }
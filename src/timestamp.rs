@@ -0,0 +1,114 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, Context};
+use chrono::{DateTime, Datelike, Duration, FixedOffset, NaiveDate, NaiveDateTime, TimeZone};
+
+/// UTC offsets used for the `W` (winter) and `S` (summer) suffixes of DSMR timestamps.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Timezone {
+    pub winter: FixedOffset,
+    pub summer: FixedOffset,
+}
+
+impl Default for Timezone {
+    /// CET/CEST, as used by Dutch and Belgian meters.
+    fn default() -> Self {
+        Timezone {
+            winter: FixedOffset::east_opt(3600).unwrap(),
+            summer: FixedOffset::east_opt(7200).unwrap(),
+        }
+    }
+}
+
+impl FromStr for Timezone {
+    type Err = anyhow::Error;
+
+    /// Parse a `winter/summer` pair of offsets, like `+01:00/+02:00`.
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let (winter, summer) = text
+            .split_once('/')
+            .ok_or_else(|| anyhow!("Cannot find '/' in timezone {text:?}"))?;
+        let winter = winter.parse()
+            .with_context(|| format!("Error parsing winter offset {winter:?}"))?;
+        let summer = summer.parse()
+            .with_context(|| format!("Error parsing summer offset {summer:?}"))?;
+        Ok(Timezone { winter, summer })
+    }
+}
+
+impl Timezone {
+    /// Parse a timestamp of the form `YYMMDDhhmmssX`, where `X` is `S` or `W`. DSMR 3 meters
+    /// leave out `X`; then the EU daylight saving rules decide which offset applies.
+    pub fn parse(&self, text: &str) -> Result<DateTime<FixedOffset>, anyhow::Error> {
+        let (local, flag) = match text.strip_suffix(['S', 'W']) {
+            Some(local) => (local, text.chars().last()),
+            None        => (text, None),
+        };
+        if local.len() != 12 {
+            return Err(anyhow!("Expected 12 digits in timestamp {text:?}"));
+        }
+        let local = NaiveDateTime::parse_from_str(local, "%y%m%d%H%M%S")
+            .with_context(|| format!("Error parsing timestamp {text:?}"))?;
+        let offset = match flag {
+            Some('S')   => self.summer,
+            Some(_)     => self.winter,
+            None        => if Self::is_summer(&local, self.winter) { self.summer } else { self.winter },
+        };
+        offset.from_local_datetime(&local)
+            .single()
+            .ok_or_else(|| anyhow!("Ambiguous timestamp {text:?}"))
+    }
+
+    // summer time runs from 01:00 UTC on the last Sunday of March to the last Sunday of October
+    fn is_summer(local: &NaiveDateTime, winter: FixedOffset) -> bool {
+        let last_sunday = |month| {
+            let last = NaiveDate::from_ymd_opt(local.year(), month, 31).unwrap();
+            last - Duration::days(last.weekday().num_days_from_sunday().into())
+        };
+        let utc = *local - Duration::seconds(winter.local_minus_utc().into());
+        let start = last_sunday(3).and_hms_opt(1, 0, 0).unwrap();
+        let end = last_sunday(10).and_hms_opt(1, 0, 0).unwrap();
+        start <= utc && utc < end
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{FixedOffset, TimeZone};
+
+    use super::Timezone;
+
+    #[test]
+    fn test_parse() -> Result<(), anyhow::Error> {
+        let tz = Timezone::default();
+        let cest = FixedOffset::east_opt(7200).unwrap();
+        let cet = FixedOffset::east_opt(3600).unwrap();
+        assert_eq!(tz.parse("220611162528S")?, cest.with_ymd_and_hms(2022, 6, 11, 16, 25, 28).unwrap());
+        assert_eq!(tz.parse("180228084605W")?, cet.with_ymd_and_hms(2018, 2, 28, 8, 46, 5).unwrap());
+        assert!(tz.parse("221311162528S").is_err());
+        assert!(tz.parse("22061116252").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_without_flag() -> Result<(), anyhow::Error> {
+        let tz = Timezone::default();
+        let cest = FixedOffset::east_opt(7200).unwrap();
+        let cet = FixedOffset::east_opt(3600).unwrap();
+        assert_eq!(tz.parse("220611162528")?, cest.with_ymd_and_hms(2022, 6, 11, 16, 25, 28).unwrap());
+        assert_eq!(tz.parse("221225120000")?, cet.with_ymd_and_hms(2022, 12, 25, 12, 0, 0).unwrap());
+        assert_eq!(tz.parse("220327015959")?, cet.with_ymd_and_hms(2022, 3, 27, 1, 59, 59).unwrap());
+        assert_eq!(tz.parse("220327030000")?, cest.with_ymd_and_hms(2022, 3, 27, 3, 0, 0).unwrap());
+        assert_eq!(tz.parse("221030040000")?, cet.with_ymd_and_hms(2022, 10, 30, 4, 0, 0).unwrap());
+        Ok(())
+    }
+
+    #[test]
+    fn test_timezone() -> Result<(), anyhow::Error> {
+        let tz: Timezone = "+00:00/+01:00".parse()?;
+        let utc = FixedOffset::east_opt(0).unwrap();
+        assert_eq!(tz.parse("221201120000W")?, utc.with_ymd_and_hms(2022, 12, 1, 12, 0, 0).unwrap());
+        assert!("+01:00".parse::<Timezone>().is_err());
+        Ok(())
+    }
+}